@@ -0,0 +1,53 @@
+use std::fmt;
+
+use reqwest::StatusCode;
+
+#[derive(Debug)]
+pub enum MpsdError {
+    /// The session changed since its etag was read, or already exists when
+    /// creating it. Read it again and reapply the change.
+    PreconditionFailed,
+    /// The session was read without an etag, so an update could overwrite
+    /// changes made since.
+    NoEtag,
+    /// The session directory answered without the session it was asked to
+    /// create.
+    EmptySession,
+    /// The session directory refused the request.
+    Status {
+        status: StatusCode,
+        body: String,
+    },
+    /// No Xbox Live token to authorize the request with.
+    Auth(anyhow::Error),
+    Http(reqwest::Error),
+}
+
+impl fmt::Display for MpsdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::PreconditionFailed => f.write_str("MPSD session was changed concurrently"),
+            Self::NoEtag => f.write_str("MPSD session has no etag to update it against"),
+            Self::EmptySession => f.write_str("MPSD answered without the created session"),
+            Self::Status { status, body } => write!(f, "MPSD request failed with {status}: {body}"),
+            Self::Auth(e) => write!(f, "Xbox Live authorization failed: {e}"),
+            Self::Http(e) => write!(f, "HTTP error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for MpsdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Auth(e) => Some(e.as_ref()),
+            Self::Http(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for MpsdError {
+    fn from(value: reqwest::Error) -> Self {
+        Self::Http(value)
+    }
+}
@@ -0,0 +1,370 @@
+use std::sync::Arc;
+
+use error::MpsdError;
+use reqwest::{
+    header::{ETAG, IF_MATCH, IF_NONE_MATCH},
+    Client, Method, RequestBuilder, Response, StatusCode,
+};
+use serde_json::json;
+use session::{Member, Session, SessionDocument, SessionRef};
+use tokio::sync::Mutex;
+use tracing::{debug, instrument};
+use xbl_auth::XBLAuth;
+
+pub mod error;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+pub mod session;
+
+const CONTRACT_VERSION: &str = "107";
+
+/// Client of the Xbox Live Multiplayer Session Directory.
+#[derive(Debug, Clone)]
+pub struct MpsdClient {
+    xbl_auth: Arc<Mutex<XBLAuth>>,
+    client: Client,
+    endpoint: String,
+}
+
+impl MpsdClient {
+    pub fn new(xbl_auth: Arc<Mutex<XBLAuth>>) -> Self {
+        Self {
+            xbl_auth,
+            client: Client::new(),
+            endpoint: "https://sessiondirectory.xboxlive.com".to_owned(),
+        }
+    }
+
+    /// Sends requests to `endpoint` instead of the session directory.
+    pub fn set_endpoint(mut self, endpoint: String) -> Self {
+        self.endpoint = endpoint;
+        self
+    }
+
+    /// `None` if there is no such session.
+    #[instrument(skip_all, fields(session = %session_ref.name))]
+    pub async fn get(&self, session_ref: &SessionRef) -> Result<Option<Session>, MpsdError> {
+        let request = self.request(Method::GET, &session_ref.path());
+        match self.send(session_ref, request).await {
+            Err(MpsdError::Status {
+                status: StatusCode::NOT_FOUND,
+                ..
+            }) => Ok(None),
+            result => result,
+        }
+    }
+
+    /// Creates the session, failing with `PreconditionFailed` if it exists.
+    #[instrument(skip_all, fields(session = %session_ref.name))]
+    pub async fn create(
+        &self,
+        session_ref: &SessionRef,
+        document: &SessionDocument,
+    ) -> Result<Session, MpsdError> {
+        let request = self
+            .request(Method::PUT, &session_ref.path())
+            .header(IF_NONE_MATCH, "*")
+            .json(document);
+        self.send(session_ref, request)
+            .await?
+            .ok_or(MpsdError::EmptySession)
+    }
+
+    /// Merges `document` into `session`, failing with `PreconditionFailed` if
+    /// the session changed since it was read, or with `NoEtag` if there is no
+    /// etag to tell. `None` once the last member has left, which deletes the
+    /// session.
+    #[instrument(skip_all, fields(session = %session.session_ref.name))]
+    pub async fn update(
+        &self,
+        session: &Session,
+        document: &SessionDocument,
+    ) -> Result<Option<Session>, MpsdError> {
+        let etag = session.etag.as_ref().ok_or(MpsdError::NoEtag)?;
+        let request = self
+            .request(Method::PUT, &session.session_ref.path())
+            .header(IF_MATCH, etag)
+            .json(document);
+        self.send(&session.session_ref, request).await
+    }
+
+    /// Merges `document` into the session whatever its state, creating it if
+    /// needed.
+    #[instrument(skip_all, fields(session = %session_ref.name))]
+    pub async fn put(
+        &self,
+        session_ref: &SessionRef,
+        document: &SessionDocument,
+    ) -> Result<Option<Session>, MpsdError> {
+        let request = self
+            .request(Method::PUT, &session_ref.path())
+            .json(document);
+        self.send(session_ref, request).await
+    }
+
+    /// Reserves a place for each of `xuids`, which lets them join a closed
+    /// session. The session directory picks the member index of every
+    /// reservation; look them up with [`SessionDocument::member_index`].
+    #[instrument(skip_all, fields(session = %session.session_ref.name))]
+    pub async fn reserve(
+        &self,
+        session: &Session,
+        xuids: &[&str],
+    ) -> Result<Option<Session>, MpsdError> {
+        let document =
+            xuids
+                .iter()
+                .enumerate()
+                .fold(SessionDocument::default(), |document, (i, xuid)| {
+                    document.set_member(format!("reserve_{i}"), Some(Member::reservation(*xuid)))
+                });
+        self.update(session, &document).await
+    }
+
+    /// Removes the member or reservation with index `member`.
+    #[instrument(skip_all, fields(session = %session.session_ref.name))]
+    pub async fn remove_member(
+        &self,
+        session: &Session,
+        member: &str,
+    ) -> Result<Option<Session>, MpsdError> {
+        let document = SessionDocument::default().set_member(member, None);
+        self.update(session, &document).await
+    }
+
+    /// Makes the caller an active member over the RTA connection
+    /// `connection_id`. Called again after the connection id changed, it moves
+    /// the membership to the new connection.
+    #[instrument(skip_all, fields(session = %session_ref.name))]
+    pub async fn join(
+        &self,
+        session_ref: &SessionRef,
+        connection_id: &str,
+    ) -> Result<Option<Session>, MpsdError> {
+        let subscription_id = uuid::Uuid::new_v4().to_string();
+        let member = Member::active(connection_id, subscription_id);
+        let document = SessionDocument::default().set_member("me", Some(member));
+        self.put(session_ref, &document).await
+    }
+
+    /// Removes the caller from the session. The session directory deletes a
+    /// session once its last member has left, and keeps it for the others
+    /// until then.
+    #[instrument(skip_all, fields(session = %session_ref.name))]
+    pub async fn leave(&self, session_ref: &SessionRef) -> Result<(), MpsdError> {
+        let path = format!("{}/members/me", session_ref.path());
+        let request = self.request(Method::DELETE, &path);
+        self.send(session_ref, request).await?;
+        Ok(())
+    }
+
+    /// Sets the session as the caller's activity, which is what friends see
+    /// and join it through.
+    #[instrument(skip_all, fields(session = %session_ref.name))]
+    pub async fn set_activity(&self, session_ref: &SessionRef) -> Result<(), MpsdError> {
+        let handle = json!({
+            "version": 1,
+            "type": "activity",
+            "sessionRef": session_ref,
+        });
+        let request = self.request(Method::POST, "/handles").json(&handle);
+        self.execute(request).await?;
+        Ok(())
+    }
+
+    fn request(&self, method: Method, path: &str) -> RequestBuilder {
+        self.client
+            .request(method, format!("{}{path}", self.endpoint))
+            .header("x-xbl-contract-version", CONTRACT_VERSION)
+    }
+
+    async fn authorization(&self) -> Result<String, MpsdError> {
+        let mut xbl_auth = self.xbl_auth.lock().await;
+        let xsts = xbl_auth.get_xbox_token().await.map_err(MpsdError::Auth)?;
+        Ok(format!("XBL3.0 x={};{}", xsts.user_hash, xsts.token))
+    }
+
+    async fn send(
+        &self,
+        session_ref: &SessionRef,
+        request: RequestBuilder,
+    ) -> Result<Option<Session>, MpsdError> {
+        let Some(response) = self.execute(request).await? else {
+            return Ok(None);
+        };
+        let etag = response
+            .headers()
+            .get(ETAG)
+            .and_then(|etag| etag.to_str().ok())
+            .map(str::to_owned);
+        Ok(Some(Session {
+            session_ref: session_ref.clone(),
+            etag,
+            document: response.json().await?,
+        }))
+    }
+
+    /// `None` for responses without content.
+    async fn execute(&self, request: RequestBuilder) -> Result<Option<Response>, MpsdError> {
+        let response = request
+            .header("authorization", self.authorization().await?)
+            .send()
+            .await?;
+        let status = response.status();
+        debug!(%status, "MPSD response");
+        match status {
+            StatusCode::NO_CONTENT => Ok(None),
+            StatusCode::PRECONDITION_FAILED => Err(MpsdError::PreconditionFailed),
+            status if !status.is_success() => {
+                let body = response.text().await.unwrap_or_default();
+                Err(MpsdError::Status { status, body })
+            }
+            _ => Ok(Some(response)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use xbl_auth::{cache::Cache, expire::Expire, request_token::XSTSToken, XBLAuth};
+
+    use crate::{
+        error::MpsdError,
+        mock::MockMpsd,
+        session::{Session, SessionDocument, SessionRef},
+        MpsdClient,
+    };
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Lobby {
+        world_name: String,
+    }
+
+    /// A client signed in with `token`, which the mock tells users apart by.
+    async fn client(mock: &MockMpsd, token: &str) -> Result<MpsdClient> {
+        let dir = std::env::temp_dir().join(format!("mpsd-{}", uuid::Uuid::new_v4().simple()));
+        let xsts = XSTSToken {
+            gamer_tag: String::new(),
+            xuid: String::new(),
+            user_hash: "1234567890".into(),
+            token: token.into(),
+        };
+        Cache::new(dir.clone(), "Ferris")
+            .update_xsts(&Expire::with_duration(xsts, 3600))
+            .await?;
+        let xbl_auth = Arc::new(tokio::sync::Mutex::new(XBLAuth::new(dir, "Ferris".into())));
+        Ok(MpsdClient::new(xbl_auth).set_endpoint(mock.uri()))
+    }
+
+    #[tokio::test]
+    async fn manages_sessions_with_etags() -> Result<()> {
+        let mock = MockMpsd::start().await?;
+        let mpsd = client(&mock, "mpsd").await?;
+        let session_ref = SessionRef::minecraft_lobby("5a1bf1a5");
+        assert!(mpsd.get(&session_ref).await?.is_none());
+
+        let lobby = Lobby {
+            world_name: "Ferris' world".into(),
+        };
+        let document = SessionDocument::default().set_custom_properties(&lobby)?;
+        let created = mpsd.create(&session_ref, &document).await?;
+        assert_eq!(created.etag.as_deref(), Some("\"1\""));
+        assert!(matches!(
+            mpsd.create(&session_ref, &document).await,
+            Err(MpsdError::PreconditionFailed)
+        ));
+
+        let joined = mpsd.join(&session_ref, "c0").await?.unwrap();
+        let members = joined.document.members.clone().unwrap();
+        let me = members["0"].as_ref().unwrap().properties.clone().unwrap();
+        assert_eq!(me.system.unwrap().connection.as_deref(), Some("c0"));
+
+        let xuids = ["2535400000000000", "2535400000000001"];
+        let reserved = mpsd.reserve(&joined, &xuids).await?.unwrap();
+        let mut indices: Vec<_> = xuids
+            .iter()
+            .map(|xuid| reserved.document.member_index(xuid).unwrap())
+            .collect();
+        indices.sort();
+        assert_eq!(indices, ["1", "2"]);
+        let index = reserved.document.member_index(xuids[0]).unwrap();
+
+        // `joined` is stale now that the reservations were added.
+        assert!(matches!(
+            mpsd.remove_member(&joined, index).await,
+            Err(MpsdError::PreconditionFailed)
+        ));
+        let removed = mpsd.remove_member(&reserved, index).await?.unwrap();
+        assert!(removed.document.member_index(xuids[0]).is_none());
+        assert!(removed.document.member_index(xuids[1]).is_some());
+
+        let read = mpsd.get(&session_ref).await?.unwrap();
+        assert_eq!(read.etag, removed.etag);
+        assert_eq!(read.document.custom_properties::<Lobby>()?, Some(lobby));
+        let unread = Session { etag: None, ..read };
+        assert!(matches!(
+            mpsd.remove_member(&unread, "2").await,
+            Err(MpsdError::NoEtag)
+        ));
+
+        mpsd.set_activity(&session_ref).await?;
+        assert_eq!(
+            mock.handles()[0]["sessionRef"],
+            json!({
+                "scid": "4fc10100-5f7a-4470-899b-280835760c07",
+                "templateName": "MinecraftLobby",
+                "name": "5a1bf1a5",
+            })
+        );
+        assert!(mock
+            .authorizations()
+            .iter()
+            .all(|a| a == "XBL3.0 x=1234567890;mpsd"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sends_large_sessions() -> Result<()> {
+        let mock = MockMpsd::start().await?;
+        let mpsd = client(&mock, "mpsd").await?;
+        let session_ref = SessionRef::minecraft_lobby("5a1bf1a5");
+        let lobby = Lobby {
+            world_name: "Ferris".repeat(32 * 1024),
+        };
+        let document = SessionDocument::default().set_custom_properties(&lobby)?;
+        mpsd.create(&session_ref, &document).await?;
+        let read = mpsd.get(&session_ref).await?.unwrap();
+        assert_eq!(read.document.custom_properties::<Lobby>()?, Some(lobby));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn leaves_sessions_to_other_members() -> Result<()> {
+        let mock = MockMpsd::start().await?;
+        let (host, guest) = (client(&mock, "host").await?, client(&mock, "guest").await?);
+        let session_ref = SessionRef::minecraft_lobby("5a1bf1a5");
+        host.join(&session_ref, "c0").await?;
+        let joined = guest.join(&session_ref, "c1").await?.unwrap();
+        let members = joined.document.members.unwrap();
+        assert_eq!(members.keys().len(), 2);
+        assert!(members.contains_key("0") && members.contains_key("1"));
+
+        host.leave(&session_ref).await?;
+        let session = guest.get(&session_ref).await?.unwrap();
+        let members = session.document.members.unwrap();
+        assert_eq!(members.keys().collect::<Vec<_>>(), ["1"]);
+
+        // Leaving twice changes nothing.
+        host.leave(&session_ref).await?;
+        assert!(guest.get(&session_ref).await?.is_some());
+        guest.leave(&session_ref).await?;
+        assert!(host.get(&session_ref).await?.is_none());
+        Ok(())
+    }
+}
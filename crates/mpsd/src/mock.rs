@@ -0,0 +1,313 @@
+//! A local stand-in for `sessiondirectory.xboxlive.com`, for tests.
+
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    task::JoinHandle,
+};
+
+use crate::{session::SessionRef, CONTRACT_VERSION};
+
+#[derive(Default)]
+struct State {
+    sessions: HashMap<String, MockSession>,
+    handles: Vec<Value>,
+    authorizations: Vec<String>,
+    // Sessions another writer changes before their next etag-checked write.
+    interfered: HashSet<String>,
+}
+
+struct MockSession {
+    etag: u64,
+    document: Value,
+    // Index the next new member gets.
+    next: u64,
+    // `authorization` header -> index of the member it joined as.
+    callers: HashMap<String, String>,
+}
+
+impl State {
+    /// Status line, etag and body of the response.
+    fn respond(
+        &mut self,
+        method: &str,
+        path: &str,
+        headers: &HashMap<String, String>,
+        body: &[u8],
+    ) -> Result<(&'static str, Option<u64>, String)> {
+        if headers.get("x-xbl-contract-version").map(String::as_str) != Some(CONTRACT_VERSION) {
+            return Ok(("400 Bad Request", None, String::new()));
+        }
+        let response = match (method, path) {
+            ("POST", "/handles") => {
+                let mut handle: Value = serde_json::from_slice(body)?;
+                handle["id"] = json!(format!("handle-{}", self.handles.len()));
+                self.handles.push(handle.clone());
+                ("201 Created", None, handle.to_string())
+            }
+            ("GET", _) => match self.sessions.get(path) {
+                Some(session) => ("200 OK", Some(session.etag), session.document.to_string()),
+                None => ("404 Not Found", None, String::new()),
+            },
+            ("DELETE", _) => {
+                let path = path.trim_end_matches("/members/me");
+                let caller = headers.get("authorization");
+                if let Some(session) = self.sessions.get_mut(path) {
+                    if let Some(index) = caller.and_then(|caller| session.callers.remove(caller)) {
+                        session.remove_member(&index);
+                        session.etag += 1;
+                    }
+                    if session.is_empty() {
+                        self.sessions.remove(path);
+                    }
+                }
+                ("204 No Content", None, String::new())
+            }
+            ("PUT", _) => self.put(path, headers, serde_json::from_slice(body)?),
+            _ => ("405 Method Not Allowed", None, String::new()),
+        };
+        Ok(response)
+    }
+
+    /// Merges `update` into the session the way the session directory does,
+    /// checking its etag first.
+    fn put(
+        &mut self,
+        path: &str,
+        headers: &HashMap<String, String>,
+        update: Value,
+    ) -> (&'static str, Option<u64>, String) {
+        if headers.contains_key("if-match") && self.interfered.remove(path) {
+            if let Some(session) = self.sessions.get_mut(path) {
+                session.etag += 1;
+            }
+        }
+        let current = self
+            .sessions
+            .get(path)
+            .map(|session| format!("\"{}\"", session.etag));
+        let conflict = match (headers.get("if-match"), headers.get("if-none-match")) {
+            (_, Some(_)) => current.is_some(),
+            (Some(etag), _) => current.as_ref() != Some(etag),
+            _ => false,
+        };
+        if conflict {
+            return ("412 Precondition Failed", None, String::new());
+        }
+        let session = self
+            .sessions
+            .entry(path.to_owned())
+            .or_insert_with(|| MockSession {
+                etag: 0,
+                document: json!({}),
+                next: 0,
+                callers: HashMap::new(),
+            });
+        let had_members = !session.is_empty();
+        for (key, value) in update.as_object().into_iter().flatten() {
+            if key == "members" {
+                for (member, value) in value.as_object().into_iter().flatten() {
+                    let caller = headers.get("authorization").cloned().unwrap_or_default();
+                    session.merge_member(member, &caller, value);
+                }
+            } else {
+                session.document[key] = value.clone();
+            }
+        }
+        session.etag += 1;
+        if had_members && session.is_empty() {
+            self.sessions.remove(path);
+            return ("204 No Content", None, String::new());
+        }
+        ("200 OK", Some(session.etag), session.document.to_string())
+    }
+}
+
+impl MockSession {
+    /// Applies the update of the member written under `key`: `me` for the
+    /// caller, `reserve_<n>` for a new reservation, or a member index. New
+    /// members get the next index, as they do in the session directory.
+    fn merge_member(&mut self, key: &str, caller: &str, value: &Value) {
+        let index = if key == "me" {
+            self.callers.get(caller).cloned()
+        } else if key.starts_with("reserve_") {
+            None
+        } else {
+            Some(key.to_owned())
+        };
+        if value.is_null() {
+            if let Some(index) = index {
+                self.callers.retain(|_, member| *member != index);
+                self.remove_member(&index);
+            }
+            return;
+        }
+        let index = index.unwrap_or_else(|| {
+            self.next += 1;
+            (self.next - 1).to_string()
+        });
+        if key == "me" {
+            self.callers.insert(caller.to_owned(), index.clone());
+        }
+        let mut members = self.document["members"]
+            .as_object()
+            .cloned()
+            .unwrap_or_default();
+        let member = members.entry(index).or_insert_with(|| json!({}));
+        for (key, value) in value.as_object().into_iter().flatten() {
+            member[key] = value.clone();
+        }
+        self.document["members"] = Value::Object(members);
+    }
+
+    fn remove_member(&mut self, index: &str) {
+        if let Some(members) = self.document["members"].as_object_mut() {
+            members.remove(index);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.document["members"]
+            .as_object()
+            .is_none_or(|members| members.is_empty())
+    }
+}
+
+/// A session directory on localhost.
+///
+/// Sessions are merged and etag-checked like the real service, and members
+/// get numeric indices, but sessions are not validated against any template.
+/// Every `authorization` header is accepted and stands for a different user,
+/// whom `me` refers to.
+pub struct MockMpsd {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    task: JoinHandle<()>,
+}
+
+impl MockMpsd {
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State::default()));
+        let task = tokio::spawn(accept(listener, state.clone()));
+        Ok(Self { addr, state, task })
+    }
+
+    /// What to pass to `MpsdClient::set_endpoint`.
+    pub fn uri(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// The session as the session directory would return it.
+    pub fn session(&self, session_ref: &SessionRef) -> Option<Value> {
+        let state = self.state.lock().unwrap();
+        let session = state.sessions.get(&session_ref.path());
+        session.map(|session| session.document.clone())
+    }
+
+    /// Drops the session, as the session directory does once its members
+    /// have gone.
+    pub fn remove_session(&self, session_ref: &SessionRef) {
+        self.state
+            .lock()
+            .unwrap()
+            .sessions
+            .remove(&session_ref.path());
+    }
+
+    /// Lets another writer change the session just before the next write
+    /// checked against its etag, which then fails with `412`.
+    pub fn interfere(&self, session_ref: &SessionRef) {
+        self.state
+            .lock()
+            .unwrap()
+            .interfered
+            .insert(session_ref.path());
+    }
+
+    /// Every handle created so far, oldest first.
+    pub fn handles(&self) -> Vec<Value> {
+        self.state.lock().unwrap().handles.clone()
+    }
+
+    /// The `authorization` header of every request so far.
+    pub fn authorizations(&self) -> Vec<String> {
+        self.state.lock().unwrap().authorizations.clone()
+    }
+}
+
+impl Drop for MockMpsd {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn accept(listener: TcpListener, state: Arc<Mutex<State>>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, state.clone()));
+    }
+}
+
+/// Answers the one request sent over `stream`.
+async fn serve<S>(mut stream: S, state: Arc<Mutex<State>>) -> Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut buf = vec![0; 64 * 1024];
+    let mut len = 0;
+    let (head, body_start) = loop {
+        let read = stream.read(&mut buf[len..]).await?;
+        anyhow::ensure!(read > 0, "Connection closed before the request ended.");
+        len += read;
+        let text = String::from_utf8_lossy(&buf[..len]);
+        if let Some(i) = text.find("\r\n\r\n") {
+            break (text[..i].to_owned(), i + 4);
+        }
+    };
+    let headers: HashMap<String, String> = head
+        .lines()
+        .skip(1)
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_owned()))
+        .collect();
+    let content_length = headers
+        .get("content-length")
+        .map(|v| v.parse::<usize>())
+        .transpose()?
+        .unwrap_or(0);
+    buf.resize(buf.len().max(body_start + content_length), 0);
+    while len < body_start + content_length {
+        let read = stream.read(&mut buf[len..]).await?;
+        anyhow::ensure!(read > 0, "Connection closed before the request body ended.");
+        len += read;
+    }
+    let mut request_line = head.split(' ');
+    let method = request_line.next().unwrap_or_default();
+    let path = request_line.next().context("No path in the request.")?;
+
+    let (status, etag, body) = {
+        let mut state = state.lock().unwrap();
+        if let Some(authorization) = headers.get("authorization") {
+            state.authorizations.push(authorization.clone());
+        }
+        state.respond(method, path, &headers, &buf[body_start..len])?
+    };
+    let etag = etag
+        .map(|etag| format!("ETag: \"{etag}\"\r\n"))
+        .unwrap_or_default();
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\n{etag}Content-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}
@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Service config of Minecraft: Bedrock Edition.
+pub const MINECRAFT_SCID: &str = "4fc10100-5f7a-4470-899b-280835760c07";
+/// Template of the lobbies Minecraft lists in the friends tab.
+pub const MINECRAFT_LOBBY_TEMPLATE: &str = "MinecraftLobby";
+
+/// Names a session within a service config and template.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionRef {
+    pub scid: String,
+    pub template_name: String,
+    pub name: String,
+}
+
+impl SessionRef {
+    pub fn new(
+        scid: impl Into<String>,
+        template_name: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Self {
+        Self {
+            scid: scid.into(),
+            template_name: template_name.into(),
+            name: name.into(),
+        }
+    }
+
+    /// A Minecraft lobby named `name`, usually a fresh UUID.
+    pub fn minecraft_lobby(name: impl Into<String>) -> Self {
+        Self::new(MINECRAFT_SCID, MINECRAFT_LOBBY_TEMPLATE, name)
+    }
+
+    /// Path of the session relative to the session directory.
+    pub fn path(&self) -> String {
+        format!(
+            "/serviceconfigs/{}/sessionTemplates/{}/sessions/{}",
+            self.scid, self.template_name, self.name
+        )
+    }
+}
+
+/// A session as the session directory last returned it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Session {
+    pub session_ref: SessionRef,
+    /// Sent as `If-Match` by `MpsdClient::update`, so an update fails instead
+    /// of overwriting a change made meanwhile.
+    pub etag: Option<String>,
+    pub document: SessionDocument,
+}
+
+/// The JSON body of a session, both as read and as written.
+///
+/// Fields left `None` are not sent, so a write only changes what it sets.
+/// Fields without a typed counterpart are kept in `other`.
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionDocument {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constants: Option<SessionConstants>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<SessionProperties>,
+    /// Keyed by member index, which the session directory assigns. Writes can
+    /// also use `me` for the caller and `reserve_<n>` for new reservations.
+    /// `None` removes the member.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub members: Option<HashMap<String, Option<Member>>>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl SessionDocument {
+    /// The custom session properties read as `T`.
+    pub fn custom_properties<T: DeserializeOwned>(&self) -> serde_json::Result<Option<T>> {
+        let custom = self.properties.as_ref().and_then(|p| p.custom.clone());
+        custom.map(serde_json::from_value).transpose()
+    }
+
+    pub fn set_custom_properties<T: Serialize>(mut self, custom: &T) -> serde_json::Result<Self> {
+        let properties = self.properties.get_or_insert_with(Default::default);
+        properties.custom = Some(serde_json::to_value(custom)?);
+        Ok(self)
+    }
+
+    /// The index of the member or reservation for `xuid`.
+    pub fn member_index(&self, xuid: &str) -> Option<&str> {
+        let mut members = self.members.iter().flatten();
+        let (index, _) = members.find(|(_, member)| {
+            member
+                .as_ref()
+                .is_some_and(|member| member.xuid() == Some(xuid))
+        })?;
+        Some(index)
+    }
+
+    /// Sets or, with `None`, removes the member under `key`.
+    pub fn set_member(mut self, key: impl Into<String>, member: Option<Member>) -> Self {
+        let members = self.members.get_or_insert_with(Default::default);
+        members.insert(key.into(), member);
+        self
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionConstants {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemConstants>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemConstants {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_members_count: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<Visibility>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub capabilities: Option<Capabilities>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Visibility {
+    Private,
+    Visible,
+    Open,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connectivity: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gameplay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crossplay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection_required_for_active_members: Option<bool>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SessionProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<SystemProperties>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SystemProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub join_restriction: Option<Restriction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read_restriction: Option<Restriction>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub closed: Option<bool>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+/// Who may join or read a session besides its members.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Restriction {
+    None,
+    Local,
+    Followed,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Member {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub constants: Option<MemberConstants>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub properties: Option<MemberProperties>,
+    /// Only set in responses.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gamertag: Option<String>,
+    #[serde(flatten)]
+    pub other: Map<String, Value>,
+}
+
+impl Member {
+    /// A reservation for `xuid`, who can then join the session.
+    pub fn reservation(xuid: impl Into<String>) -> Self {
+        Self {
+            constants: Some(MemberConstants {
+                system: Some(MemberSystemConstants {
+                    xuid: Some(xuid.into()),
+                    initialize: Some(true),
+                }),
+                custom: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    /// The caller as an active member, tied to the RTA connection
+    /// `connection_id` so the session directory sends it shoulder taps and
+    /// removes it when the connection drops.
+    pub fn active(connection_id: impl Into<String>, subscription_id: impl Into<String>) -> Self {
+        Self {
+            constants: Some(MemberConstants {
+                system: Some(MemberSystemConstants {
+                    xuid: None,
+                    initialize: Some(true),
+                }),
+                custom: None,
+            }),
+            properties: Some(MemberProperties {
+                system: Some(MemberSystemProperties {
+                    active: Some(true),
+                    connection: Some(connection_id.into()),
+                    subscription: Some(MemberSubscription {
+                        id: subscription_id.into(),
+                        change_types: vec!["everything".to_owned()],
+                    }),
+                }),
+                custom: None,
+            }),
+            ..Default::default()
+        }
+    }
+
+    pub fn xuid(&self) -> Option<&str> {
+        let system = self.constants.as_ref()?.system.as_ref()?;
+        system.xuid.as_deref()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberConstants {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<MemberSystemConstants>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberSystemConstants {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub xuid: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub initialize: Option<bool>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<MemberSystemProperties>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub custom: Option<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberSystemProperties {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub active: Option<bool>,
+    /// RTA `ConnectionId` of the member.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subscription: Option<MemberSubscription>,
+}
+
+/// Which session changes are sent to the member as shoulder taps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MemberSubscription {
+    pub id: String,
+    pub change_types: Vec<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+
+    use super::{Member, SessionDocument, SessionRef};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Lobby {
+        host_name: String,
+    }
+
+    #[test]
+    fn documents_round_trip() -> Result<()> {
+        let session = SessionRef::minecraft_lobby("5a1bf1a5");
+        assert_eq!(
+            session.path(),
+            "/serviceconfigs/4fc10100-5f7a-4470-899b-280835760c07/sessionTemplates/MinecraftLobby/sessions/5a1bf1a5"
+        );
+
+        let document = SessionDocument::default()
+            .set_custom_properties(&Lobby {
+                host_name: "Ferris".into(),
+            })?
+            .set_member("me", Some(Member::active("c0", "s0")))
+            .set_member("reserve_0", Some(Member::reservation("2535400000000000")))
+            .set_member("1", None);
+        let value = serde_json::to_value(&document)?;
+        assert_eq!(
+            value["properties"],
+            json!({ "custom": { "hostName": "Ferris" } })
+        );
+        assert_eq!(value["members"]["1"], json!(null));
+        assert_eq!(
+            value["members"]["me"]["properties"]["system"],
+            json!({
+                "active": true,
+                "connection": "c0",
+                "subscription": { "id": "s0", "changeTypes": ["everything"] }
+            })
+        );
+        assert_eq!(
+            value["members"]["reserve_0"]["constants"]["system"],
+            json!({ "xuid": "2535400000000000", "initialize": true })
+        );
+
+        let read: SessionDocument = serde_json::from_value(json!({
+            "properties": { "system": { "joinRestriction": "followed" }, "custom": { "hostName": "Ferris" } },
+            "correlationId": "7d1e0c5a",
+        }))?;
+        assert_eq!(read.other["correlationId"], "7d1e0c5a");
+        assert_eq!(
+            read.custom_properties::<Lobby>()?.unwrap().host_name,
+            "Ferris"
+        );
+        Ok(())
+    }
+}
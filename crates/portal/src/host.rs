@@ -0,0 +1,464 @@
+use std::{sync::Arc, time::Duration};
+
+use anyhow::{bail, ensure, Result};
+use mpsd::{error::MpsdError, session::SessionRef, MpsdClient};
+use real_time_activity::{builder::RtaClientBuilder, handle::RtaHandle, resource::RtaResource};
+use tokio::{
+    select,
+    sync::{oneshot, watch, Mutex},
+    task::JoinHandle,
+    time::{interval_at, timeout, Instant},
+};
+use tracing::{debug, info, info_span, warn, Instrument};
+use xbl_auth::XBLAuth;
+
+use crate::lobby::ServerInfo;
+
+const CONNECTION_ID_TIMEOUT: Duration = Duration::from_secs(30);
+const RTA_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+// Writes of the lobby that lose to concurrent changes before giving up until
+// the next refresh.
+const PUBLISH_ATTEMPTS: u32 = 3;
+
+pub struct FriendConnectBuilder {
+    xbl_auth: Arc<Mutex<XBLAuth>>,
+    server: ServerInfo,
+    rta: RtaClientBuilder,
+    mpsd: MpsdClient,
+    refresh_interval: Duration,
+}
+
+impl FriendConnectBuilder {
+    pub fn new(xbl_auth: Arc<Mutex<XBLAuth>>, server: ServerInfo) -> Self {
+        Self {
+            rta: RtaClientBuilder::new(xbl_auth.clone()),
+            mpsd: MpsdClient::new(xbl_auth.clone()),
+            xbl_auth,
+            server,
+            refresh_interval: Duration::from_secs(60),
+        }
+    }
+
+    /// Builder of the RTA connection the lobby membership is bound to.
+    pub fn set_rta(mut self, rta: RtaClientBuilder) -> Self {
+        self.rta = rta;
+        self
+    }
+
+    pub fn set_mpsd(mut self, mpsd: MpsdClient) -> Self {
+        self.mpsd = mpsd;
+        self
+    }
+
+    /// How often the lobby is written again, which recreates it should the
+    /// session directory have dropped it. Must not be zero; `start` fails
+    /// otherwise.
+    pub fn set_refresh_interval(mut self, interval: Duration) -> Self {
+        self.refresh_interval = interval;
+        self
+    }
+
+    /// Connects to RTA, then creates the lobby and sets it as the activity of
+    /// the signed-in user.
+    pub async fn start(self) -> Result<FriendConnectHost> {
+        ensure!(
+            !self.refresh_interval.is_zero(),
+            "The refresh interval is zero."
+        );
+        let xuid = self
+            .xbl_auth
+            .lock()
+            .await
+            .get_xbox_token()
+            .await?
+            .xuid
+            .clone();
+        let (rta, _) = self
+            .rta
+            .add_resource(&RtaResource::Connections)
+            .connect()
+            .await?
+            .listen()?;
+
+        let mut connection_ids = rta.watch_connection_id();
+        let connection_id = timeout(
+            CONNECTION_ID_TIMEOUT,
+            connection_ids.wait_for(Option::is_some),
+        )
+        .await
+        .ok()
+        .and_then(|id| id.ok().and_then(|id| id.clone()));
+        let Some(connection_id) = connection_id else {
+            rta.shutdown(RTA_SHUTDOWN_TIMEOUT).await;
+            bail!("RTA sent no connection id.");
+        };
+
+        let lobby = Lobby {
+            mpsd: self.mpsd,
+            session_ref: SessionRef::minecraft_lobby(uuid::Uuid::new_v4().to_string()),
+            xuid,
+            subscription_id: uuid::Uuid::new_v4().to_string(),
+        };
+        let span = info_span!("friend_connect", session = %lobby.session_ref.name);
+        if let Err(e) = lobby
+            .create(&self.server, &connection_id)
+            .instrument(span.clone())
+            .await
+        {
+            rta.shutdown(RTA_SHUTDOWN_TIMEOUT).await;
+            return Err(e);
+        }
+
+        let session_ref = lobby.session_ref.clone();
+        let server = watch::Sender::new(self.server);
+        let (shutdown, shutdown_rx) = oneshot::channel();
+        let run = run(
+            lobby,
+            rta,
+            connection_ids,
+            server.subscribe(),
+            shutdown_rx,
+            self.refresh_interval,
+        );
+        Ok(FriendConnectHost {
+            session_ref,
+            server,
+            shutdown,
+            task: tokio::spawn(run.instrument(span)),
+        })
+    }
+}
+
+/// A Minecraft lobby listed in the friends tab of the signed-in user's
+/// friends, pointing them to a server.
+///
+/// The lobby stays up until [`FriendConnectHost::shutdown`], or until the host
+/// is dropped.
+#[derive(Debug)]
+pub struct FriendConnectHost {
+    session_ref: SessionRef,
+    server: watch::Sender<ServerInfo>,
+    shutdown: oneshot::Sender<()>,
+    task: JoinHandle<Result<()>>,
+}
+
+impl FriendConnectHost {
+    /// Publishes a lobby for `server` with the default RTA and session
+    /// directory endpoints.
+    pub async fn start(xbl_auth: Arc<Mutex<XBLAuth>>, server: ServerInfo) -> Result<Self> {
+        FriendConnectBuilder::new(xbl_auth, server).start().await
+    }
+
+    pub fn session_ref(&self) -> &SessionRef {
+        &self.session_ref
+    }
+
+    pub fn server(&self) -> ServerInfo {
+        self.server.borrow().clone()
+    }
+
+    /// Changes the published server, e.g. its player count.
+    pub fn update_server(&self, update: impl FnOnce(&mut ServerInfo)) {
+        self.server.send_modify(update);
+    }
+
+    /// Whether the lobby was taken down.
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Leaves the lobby, which removes it, and closes the RTA connection.
+    pub async fn shutdown(self) -> Result<()> {
+        let _ = self.shutdown.send(());
+        self.task.await?
+    }
+}
+
+struct Lobby {
+    mpsd: MpsdClient,
+    session_ref: SessionRef,
+    xuid: String,
+    subscription_id: String,
+}
+
+impl Lobby {
+    async fn create(&self, server: &ServerInfo, connection_id: &str) -> Result<()> {
+        let document = server.lobby_document(&self.xuid, connection_id, &self.subscription_id)?;
+        self.mpsd.create(&self.session_ref, &document).await?;
+        self.mpsd.set_activity(&self.session_ref).await?;
+        info!(connection_id, "created lobby");
+        Ok(())
+    }
+
+    /// Writes the whole lobby over the session as it was read, creating it
+    /// again if it is gone. Only a recreated lobby needs its activity handle
+    /// set again.
+    async fn publish(&self, server: &ServerInfo, connection_id: &str) -> Result<()> {
+        let document = server.lobby_document(&self.xuid, connection_id, &self.subscription_id)?;
+        for attempt in 1..=PUBLISH_ATTEMPTS {
+            let written = match self.mpsd.get(&self.session_ref).await? {
+                Some(session) => self.mpsd.update(&session, &document).await.map(|_| false),
+                None => self
+                    .mpsd
+                    .create(&self.session_ref, &document)
+                    .await
+                    .map(|_| true),
+            };
+            match written {
+                Ok(recreated) => {
+                    if recreated {
+                        info!(connection_id, "recreated lobby");
+                        self.mpsd.set_activity(&self.session_ref).await?;
+                    }
+                    return Ok(());
+                }
+                // Read it again and write over the change.
+                Err(MpsdError::PreconditionFailed) => {
+                    debug!(attempt, "lobby changed while publishing");
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+        bail!("The lobby kept changing while it was published.")
+    }
+}
+
+async fn run(
+    lobby: Lobby,
+    rta: RtaHandle,
+    mut connection_ids: watch::Receiver<Option<String>>,
+    mut server: watch::Receiver<ServerInfo>,
+    mut shutdown: oneshot::Receiver<()>,
+    refresh_interval: Duration,
+) -> Result<()> {
+    let mut refresh = interval_at(Instant::now() + refresh_interval, refresh_interval);
+    loop {
+        select! {
+            biased;
+            // Also taken when the host is dropped.
+            _ = &mut shutdown => break,
+            changed = connection_ids.changed() => {
+                if changed.is_err() {
+                    warn!("RTA client stopped");
+                    break;
+                }
+            }
+            _ = server.changed() => {}
+            _ = refresh.tick() => {}
+        }
+        // `None` while RTA reconnects; the new id follows.
+        let Some(connection_id) = connection_ids.borrow_and_update().clone() else {
+            continue;
+        };
+        let server = server.borrow_and_update().clone();
+        if let Err(e) = lobby.publish(&server, &connection_id).await {
+            warn!(error = format!("{e:#}"), "failed to publish lobby");
+        }
+    }
+
+    let left = lobby.mpsd.leave(&lobby.session_ref).await;
+    let termination = rta.shutdown(RTA_SHUTDOWN_TIMEOUT).await;
+    info!(%termination, "closed lobby");
+    Ok(left?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::{Context, Result};
+    use mpsd::{mock::MockMpsd, session::Restriction, MpsdClient};
+    use real_time_activity::{
+        backoff::Backoff,
+        builder::RtaClientBuilder,
+        mock::{self, MockRta, Reply},
+        resource::RtaResource,
+    };
+    use serde_json::{json, Value};
+
+    use super::FriendConnectBuilder;
+    use crate::lobby::ServerInfo;
+
+    fn server() -> ServerInfo {
+        ServerInfo {
+            host_name: "Ferris".into(),
+            world_name: "Crab Rave".into(),
+            version: "1.21.2".into(),
+            protocol: 686,
+            players: 0,
+            max_players: 10,
+            join_restriction: Restriction::Followed,
+            address: "203.0.113.7".into(),
+            port: 19132,
+        }
+    }
+
+    /// Waits for the session to satisfy `check`.
+    async fn session_where(
+        mpsd: &MockMpsd,
+        session_ref: &mpsd::session::SessionRef,
+        check: impl Fn(&Value) -> bool,
+    ) -> Result<Value> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                if let Some(session) = mpsd.session(session_ref).filter(&check) {
+                    return session;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("Session never matched.")
+    }
+
+    #[tokio::test]
+    async fn publishes_lobbies_bound_to_the_rta_connection() -> Result<()> {
+        let rta = MockRta::start().await?;
+        let connections = RtaResource::Connections.uri();
+        rta.set_reply(
+            &connections,
+            Reply::success(json!({ "ConnectionId": "c0" })),
+        );
+        let mpsd = MockMpsd::start().await?;
+        let xbl_auth = mock::xbl_auth().await?;
+        let fast = Backoff {
+            initial: Duration::from_millis(10),
+            ..Default::default()
+        };
+        let host = FriendConnectBuilder::new(xbl_auth.clone(), server())
+            .set_rta(
+                RtaClientBuilder::new(xbl_auth.clone())
+                    .set_uri(rta.uri())
+                    .set_reconnect_backoff(fast),
+            )
+            .set_mpsd(MpsdClient::new(xbl_auth).set_endpoint(mpsd.uri()))
+            .start()
+            .await?;
+        let session_ref = host.session_ref().clone();
+
+        let session = mpsd.session(&session_ref).context("No lobby.")?;
+        let custom = &session["properties"]["custom"];
+        assert_eq!(custom["hostName"], "Ferris");
+        assert_eq!(custom["ownerId"], "2535400000000000");
+        assert_eq!(custom["Joinability"], "joinable_by_friends");
+        assert_eq!(
+            custom["SupportedConnections"],
+            json!([{
+                "ConnectionType": 6,
+                "HostIpAddress": "203.0.113.7",
+                "HostPort": 19132,
+                "RakNetGUID": "",
+            }])
+        );
+        assert_eq!(
+            session["properties"]["system"]["joinRestriction"],
+            "followed"
+        );
+        assert_eq!(
+            session["properties"]["system"]["readRestriction"],
+            "followed"
+        );
+        let me = &session["members"]["0"];
+        assert_eq!(me["constants"]["system"]["xuid"], "2535400000000000");
+        assert_eq!(me["properties"]["system"]["connection"], "c0");
+        assert_eq!(mpsd.handles()[0]["type"], "activity");
+        assert_eq!(mpsd.handles()[0]["sessionRef"]["name"], session_ref.name);
+
+        host.update_server(|server| server.players = 3);
+        session_where(&mpsd, &session_ref, |s| {
+            s["properties"]["custom"]["MemberCount"] == 3
+        })
+        .await?;
+
+        // A new connection id moves the membership over.
+        rta.set_reply(
+            &connections,
+            Reply::success(json!({ "ConnectionId": "c1" })),
+        );
+        rta.disconnect();
+        session_where(&mpsd, &session_ref, |s| {
+            s["members"]["0"]["properties"]["system"]["connection"] == "c1"
+        })
+        .await?;
+
+        host.shutdown().await?;
+        assert!(mpsd.session(&session_ref).is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn publishes_over_concurrent_changes() -> Result<()> {
+        let rta = MockRta::start().await?;
+        rta.set_reply(
+            &RtaResource::Connections.uri(),
+            Reply::success(json!({ "ConnectionId": "c0" })),
+        );
+        let mpsd = MockMpsd::start().await?;
+        let xbl_auth = mock::xbl_auth().await?;
+        let host = FriendConnectBuilder::new(xbl_auth.clone(), server())
+            .set_rta(RtaClientBuilder::new(xbl_auth.clone()).set_uri(rta.uri()))
+            .set_mpsd(MpsdClient::new(xbl_auth).set_endpoint(mpsd.uri()))
+            .start()
+            .await?;
+        let session_ref = host.session_ref().clone();
+
+        // Only the change itself publishes before the next refresh.
+        mpsd.interfere(&session_ref);
+        host.update_server(|server| server.players = 3);
+        session_where(&mpsd, &session_ref, |s| {
+            s["properties"]["custom"]["MemberCount"] == 3
+        })
+        .await?;
+        host.shutdown().await?;
+        assert_eq!(mpsd.handles().len(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn sets_the_activity_again_only_for_recreated_lobbies() -> Result<()> {
+        let rta = MockRta::start().await?;
+        rta.set_reply(
+            &RtaResource::Connections.uri(),
+            Reply::success(json!({ "ConnectionId": "c0" })),
+        );
+        let mpsd = MockMpsd::start().await?;
+        let xbl_auth = mock::xbl_auth().await?;
+        let builder = FriendConnectBuilder::new(xbl_auth.clone(), server())
+            .set_rta(RtaClientBuilder::new(xbl_auth.clone()).set_uri(rta.uri()))
+            .set_mpsd(MpsdClient::new(xbl_auth).set_endpoint(mpsd.uri()));
+        let zero = builder.set_refresh_interval(Duration::ZERO).start().await;
+        assert_eq!(
+            zero.unwrap_err().to_string(),
+            "The refresh interval is zero."
+        );
+        assert_eq!(rta.accepted(), 0);
+
+        let xbl_auth = mock::xbl_auth().await?;
+        let host = FriendConnectBuilder::new(xbl_auth.clone(), server())
+            .set_rta(RtaClientBuilder::new(xbl_auth.clone()).set_uri(rta.uri()))
+            .set_mpsd(MpsdClient::new(xbl_auth).set_endpoint(mpsd.uri()))
+            .set_refresh_interval(Duration::from_millis(20))
+            .start()
+            .await?;
+        let session_ref = host.session_ref().clone();
+        host.update_server(|server| server.players = 3);
+        session_where(&mpsd, &session_ref, |s| {
+            s["properties"]["custom"]["MemberCount"] == 3
+        })
+        .await?;
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(mpsd.handles().len(), 1);
+
+        mpsd.remove_session(&session_ref);
+        session_where(&mpsd, &session_ref, |_| true).await?;
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while mpsd.handles().len() < 2 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await?;
+        host.shutdown().await?;
+        assert_eq!(mpsd.handles().len(), 2);
+        Ok(())
+    }
+}
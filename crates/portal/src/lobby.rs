@@ -0,0 +1,161 @@
+use mpsd::session::{
+    Member, MemberSystemConstants, Restriction, SessionDocument, SessionProperties,
+    SystemProperties,
+};
+use serde::{Deserialize, Serialize};
+
+/// `ConnectionType` of a server reached over RakNet at an IP address and port.
+///
+/// Minecraft documents none of the lobby values; this one, like `TitleId`,
+/// `TransportLayer` and `LanGame` below, is what existing friend-connect
+/// hosts such as MCXboxBroadcast send for direct connections.
+pub const CONNECTION_TYPE_UDP: u32 = 6;
+
+/// The server a lobby points friends to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo {
+    /// Shown as the lobby's owner in the friends tab.
+    pub host_name: String,
+    pub world_name: String,
+    /// Game version, e.g. `1.21.2`.
+    pub version: String,
+    /// Network protocol version matching `version`.
+    pub protocol: u32,
+    pub players: u32,
+    pub max_players: u32,
+    pub join_restriction: Restriction,
+    pub address: String,
+    pub port: u16,
+}
+
+impl ServerInfo {
+    /// The custom session properties Minecraft reads a lobby from.
+    pub fn lobby_properties(&self, owner_xuid: &str) -> LobbyProperties {
+        // `BroadcastSetting` is 1 for invite only, 2 for friends and 3 for
+        // friends of friends.
+        let (joinability, broadcast_setting) = match self.join_restriction {
+            Restriction::Local => ("invite_only", 1),
+            Restriction::Followed => ("joinable_by_friends", 2),
+            Restriction::None => ("joinable_by_friends", 3),
+        };
+        LobbyProperties {
+            joinability: joinability.to_owned(),
+            broadcast_setting,
+            host_name: self.host_name.clone(),
+            owner_id: owner_xuid.to_owned(),
+            rak_net_guid: String::new(),
+            version: self.version.clone(),
+            protocol: self.protocol,
+            level_id: "level".to_owned(),
+            world_name: self.world_name.clone(),
+            world_type: "Survival".to_owned(),
+            member_count: self.players,
+            max_member_count: self.max_players,
+            // Set by existing hosts for servers reached directly.
+            lan_game: true,
+            online_cross_platform_game: true,
+            cross_play_disabled: false,
+            title_id: 0,
+            // RakNet.
+            transport_layer: 0,
+            supported_connections: vec![SupportedConnection {
+                connection_type: CONNECTION_TYPE_UDP,
+                host_ip_address: self.address.clone(),
+                host_port: self.port,
+                rak_net_guid: String::new(),
+            }],
+        }
+    }
+
+    /// Who may see the lobby: friends of friends only if they may join it, and
+    /// otherwise friends, who are shown invite-only lobbies too.
+    fn read_restriction(&self) -> Restriction {
+        match self.join_restriction {
+            Restriction::None => Restriction::None,
+            Restriction::Local | Restriction::Followed => Restriction::Followed,
+        }
+    }
+
+    /// The whole lobby session with the caller as its only member, active
+    /// over the RTA connection `connection_id`.
+    pub fn lobby_document(
+        &self,
+        owner_xuid: &str,
+        connection_id: &str,
+        subscription_id: &str,
+    ) -> serde_json::Result<SessionDocument> {
+        let mut me = Member::active(connection_id, subscription_id);
+        if let Some(constants) = &mut me.constants {
+            constants.system = Some(MemberSystemConstants {
+                xuid: Some(owner_xuid.to_owned()),
+                initialize: Some(true),
+            });
+        }
+        let document = SessionDocument {
+            properties: Some(SessionProperties {
+                system: Some(SystemProperties {
+                    join_restriction: Some(self.join_restriction),
+                    read_restriction: Some(self.read_restriction()),
+                    closed: Some(false),
+                    other: Default::default(),
+                }),
+                custom: None,
+            }),
+            ..Default::default()
+        };
+        Ok(document
+            .set_custom_properties(&self.lobby_properties(owner_xuid))?
+            .set_member("me", Some(me)))
+    }
+}
+
+/// Custom properties of a `MinecraftLobby` session, named as the game expects
+/// them.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LobbyProperties {
+    #[serde(rename = "Joinability")]
+    pub joinability: String,
+    #[serde(rename = "BroadcastSetting")]
+    pub broadcast_setting: u32,
+    #[serde(rename = "hostName")]
+    pub host_name: String,
+    #[serde(rename = "ownerId")]
+    pub owner_id: String,
+    #[serde(rename = "rakNetGUID")]
+    pub rak_net_guid: String,
+    pub version: String,
+    pub protocol: u32,
+    #[serde(rename = "levelId")]
+    pub level_id: String,
+    #[serde(rename = "worldName")]
+    pub world_name: String,
+    #[serde(rename = "worldType")]
+    pub world_type: String,
+    #[serde(rename = "MemberCount")]
+    pub member_count: u32,
+    #[serde(rename = "MaxMemberCount")]
+    pub max_member_count: u32,
+    #[serde(rename = "LanGame")]
+    pub lan_game: bool,
+    #[serde(rename = "OnlineCrossPlatformGame")]
+    pub online_cross_platform_game: bool,
+    #[serde(rename = "CrossPlayDisabled")]
+    pub cross_play_disabled: bool,
+    #[serde(rename = "TitleId")]
+    pub title_id: u32,
+    #[serde(rename = "TransportLayer")]
+    pub transport_layer: u32,
+    #[serde(rename = "SupportedConnections")]
+    pub supported_connections: Vec<SupportedConnection>,
+}
+
+/// How to reach the server of a lobby.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SupportedConnection {
+    pub connection_type: u32,
+    pub host_ip_address: String,
+    pub host_port: u16,
+    #[serde(rename = "RakNetGUID")]
+    pub rak_net_guid: String,
+}
@@ -0,0 +1,43 @@
+use std::time::Duration;
+
+/// Exponential backoff between retries of a failed operation.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    pub initial: Duration,
+    pub max: Duration,
+    pub multiplier: u32,
+    /// `None` retries forever.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial: Duration::from_secs(1),
+            max: Duration::from_secs(60),
+            multiplier: 2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    /// Default for retrying subscriptions, which gives up sooner than
+    /// reconnecting does.
+    pub(crate) fn subscribe() -> Self {
+        Self {
+            max_attempts: Some(5),
+            ..Default::default()
+        }
+    }
+
+    /// Delay before retry number `attempt` (starting at 0), or `None` once the
+    /// attempts are exhausted.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts.is_some_and(|max| attempt >= max) {
+            return None;
+        }
+        let factor = self.multiplier.saturating_pow(attempt);
+        Some(self.initial.saturating_mul(factor).min(self.max))
+    }
+}
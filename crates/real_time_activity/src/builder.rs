@@ -1,18 +1,42 @@
-use std::sync::Arc;
+use std::{convert::Infallible, path::Path, sync::Arc, time::Duration};
 
-use anyhow::Result;
-use futures_util::StreamExt;
-use tokio::sync::{mpsc, Mutex};
-use tokio_tungstenite::{connect_async, tungstenite::ClientRequestBuilder};
-use xbl_auth::XBLAuth;
+use anyhow::{ensure, Result};
+use futures_util::{future, sink, stream, SinkExt, StreamExt};
+use reqwest::Client;
+use serde_json::Value;
+use tokio::{sync::Mutex, time::Instant};
+use tokio_tungstenite::tungstenite::{client::IntoClientRequest, ClientRequestBuilder, Error};
+use tracing::{debug, info, instrument};
+use xbl_auth::{now_secs, XBLAuth};
 
-use crate::{RtaClient, WSWriter};
+use crate::{
+    backoff::Backoff,
+    bus::{EventBus, OverflowPolicy},
+    message::Request,
+    record::{self, Direction, Recorder},
+    resource::RtaResource,
+    transport::{FrameSink, FrameStream, RtaTransport, TcpTransport},
+    RtaClient, WSWriter,
+};
 
+/// The longest token refresh margin: half the 16 hours an XSTS token lasts.
+pub const MAX_TOKEN_REFRESH_MARGIN: Duration = Duration::from_secs(8 * 60 * 60);
+
+#[derive(Clone)]
 pub struct RtaClientBuilder {
     xbl_auth: Arc<Mutex<XBLAuth>>,
     uri: String,
     ev_bounds: usize,
+    overflow_policy: OverflowPolicy,
     subscription_urls: Vec<String>,
+    reconnect_backoff: Backoff,
+    subscribe_backoff: Backoff,
+    request_timeout: Duration,
+    ping_interval: Duration,
+    max_missed_pongs: u32,
+    token_refresh_margin: Duration,
+    recorder: Option<Recorder>,
+    transport: Arc<dyn RtaTransport>,
 }
 
 impl RtaClientBuilder {
@@ -22,6 +46,15 @@ impl RtaClientBuilder {
             uri: "".to_owned(),
             subscription_urls: vec![],
             ev_bounds: 32,
+            overflow_policy: OverflowPolicy::default(),
+            reconnect_backoff: Backoff::default(),
+            subscribe_backoff: Backoff::subscribe(),
+            request_timeout: Duration::from_secs(10),
+            ping_interval: Duration::from_secs(20),
+            max_missed_pongs: 3,
+            token_refresh_margin: Duration::from_secs(300),
+            recorder: None,
+            transport: Arc::new(TcpTransport),
         }
     }
 
@@ -35,35 +68,269 @@ impl RtaClientBuilder {
         self
     }
 
+    /// What event streams do once `ev_bounds` events are waiting to be read.
+    pub fn set_overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
     pub fn add_subscription(mut self, uri: String) -> Self {
         self.subscription_urls.push(uri);
         self
     }
 
+    #[inline]
+    pub fn add_resource(self, resource: &RtaResource) -> Self {
+        self.add_subscription(resource.uri())
+    }
+
+    pub fn set_reconnect_backoff(mut self, backoff: Backoff) -> Self {
+        self.reconnect_backoff = backoff;
+        self
+    }
+
+    /// Paces retries of subscriptions RTA refused as throttled or unavailable.
+    /// `WSWriter::subscribe` only retries within its request timeout, and
+    /// returns the refusal once the next retry would come too late.
+    pub fn set_subscribe_backoff(mut self, backoff: Backoff) -> Self {
+        self.subscribe_backoff = backoff;
+        self
+    }
+
+    /// How long `WSWriter::subscribe` waits for RTA to answer, retries
+    /// included.
+    pub fn set_request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = timeout;
+        self
+    }
+
+    /// Must not be zero; `connect` fails otherwise.
+    pub fn set_ping_interval(mut self, interval: Duration) -> Self {
+        self.ping_interval = interval;
+        self
+    }
+
+    /// How many pings in a row may go unanswered before the connection is
+    /// considered dead.
+    pub fn set_max_missed_pongs(mut self, max_missed_pongs: u32) -> Self {
+        self.max_missed_pongs = max_missed_pongs;
+        self
+    }
+
+    /// How long before its XSTS token expires a connection is replaced with
+    /// one using a fresh token. `connect` fails above
+    /// [`MAX_TOKEN_REFRESH_MARGIN`], and the margin is cut to half the
+    /// lifetime of tokens that turn out to be shorter lived.
+    pub fn set_token_refresh_margin(mut self, margin: Duration) -> Self {
+        self.token_refresh_margin = margin;
+        self
+    }
+
+    /// Writes every frame of every connection to `recorder`.
+    pub fn set_recorder(mut self, recorder: Recorder) -> Self {
+        self.recorder = Some(recorder);
+        self
+    }
+
+    /// Opens connections with `transport` instead of [`TcpTransport`].
+    pub fn set_transport(mut self, transport: impl RtaTransport + 'static) -> Self {
+        self.transport = Arc::new(transport);
+        self
+    }
+
     pub async fn connect(self) -> Result<RtaClient> {
+        let bus = self.event_bus();
+        self.connect_to(bus).await
+    }
+
+    pub(crate) fn event_bus(&self) -> EventBus {
+        EventBus::new(self.ev_bounds, self.overflow_policy)
+    }
+
+    /// Drops the subscriptions added with `add_subscription`.
+    pub(crate) fn clear_subscriptions(mut self) -> Self {
+        self.subscription_urls.clear();
+        self
+    }
+
+    fn validate(&self) -> Result<()> {
+        ensure!(!self.ping_interval.is_zero(), "The ping interval is zero.");
+        ensure!(
+            self.token_refresh_margin <= MAX_TOKEN_REFRESH_MARGIN,
+            "The token refresh margin is over {MAX_TOKEN_REFRESH_MARGIN:?}."
+        );
+        Ok(())
+    }
+
+    /// Connects, publishing events to `bus`.
+    pub(crate) async fn connect_to(self, bus: EventBus) -> Result<RtaClient> {
+        self.validate()?;
         let Self {
             xbl_auth,
             uri,
             subscription_urls,
-            ev_bounds,
+            reconnect_backoff,
+            subscribe_backoff,
+            request_timeout,
+            ping_interval,
+            max_missed_pongs,
+            token_refresh_margin,
+            recorder,
+            transport,
+            // Already part of `bus`.
+            ev_bounds: _,
+            overflow_policy: _,
         } = self;
-        let (rta_writer, rta_reader) = mpsc::channel(ev_bounds);
-        let authorization = {
-            let xsts = xbl_auth.lock().await.get_xbox_token().await?.take();
-            format!("XBL3.0 x={};{}", xsts.user_hash, xsts.token)
+        let user_name = xbl_auth.lock().await.user_name.clone();
+        let connector = Connector {
+            xbl_auth,
+            user_name,
+            uri,
+            client: Client::new(),
+            token_refresh_margin,
+            recorder,
+            transport,
         };
-        let builder = ClientRequestBuilder::new(uri.parse()?)
-            .with_header("authorization", &authorization)
-            .with_sub_protocol("rta.xboxlive.com.V2");
-        let (socket, _) = connect_async(builder).await?;
-        let (ws_writer, ws_reader) = socket.split();
-        println!("Open RTA connection");
+        let Connection {
+            writer,
+            reader,
+            refresh_at,
+        } = connector.connect().await?;
         Ok(RtaClient {
             subscription_urls,
-            ws_writer: WSWriter::new(Arc::new(Mutex::new(ws_writer))),
-            ws_reader,
-            rta_writer,
-            rta_reader,
+            connector: Some(connector),
+            reconnect_backoff,
+            ping_interval,
+            max_missed_pongs,
+            ws_writer: WSWriter::new(writer, request_timeout, bus),
+            ws_reader: reader,
+            refresh_at: Some(refresh_at),
+            subscribe_backoff,
+            overflow: None,
         })
     }
+
+    /// A client reading the inbound frames of a recording (see [`Recorder`])
+    /// instead of connecting, stopping at its end.
+    ///
+    /// Frames the client sends go nowhere. Instead of the subscriptions added
+    /// here, the recorded subscribe and unsubscribe requests are registered in
+    /// order under their recorded sequence ids, each just before the inbound
+    /// frames that followed it, so that the recorded answers resolve them across
+    /// reconnects too. Frames are read back to back: the times they were
+    /// recorded at are ignored.
+    pub fn replay(self, path: impl AsRef<Path>) -> Result<RtaClient> {
+        self.validate()?;
+        let frames = record::read(path)?;
+        let writer = sink::drain().sink_map_err(|never: Infallible| -> Error { match never {} });
+        let bus = self.event_bus();
+        let ws_writer = WSWriter::new(FrameSink::new(writer), self.request_timeout, bus);
+        let requests = ws_writer.clone();
+        // Read lazily, so that every request is registered once the frames
+        // before it are handled.
+        let inbound = stream::iter(frames).filter_map(move |frame| {
+            let message = match (frame.direction, frame.frame) {
+                (Direction::Inbound, frame) => Some(Ok(frame.into_message())),
+                (Direction::Outbound, record::Frame::Text(text)) => {
+                    if let Ok(request) = serde_json::from_str::<Request>(&text) {
+                        requests.replay_request(request);
+                    }
+                    None
+                }
+                (Direction::Outbound, _) => None,
+            };
+            future::ready(message)
+        });
+        Ok(RtaClient {
+            subscription_urls: vec![],
+            connector: None,
+            reconnect_backoff: self.reconnect_backoff,
+            ping_interval: self.ping_interval,
+            max_missed_pongs: self.max_missed_pongs,
+            ws_writer,
+            ws_reader: FrameStream::new(inbound),
+            refresh_at: None,
+            subscribe_backoff: self.subscribe_backoff,
+            overflow: None,
+        })
+    }
+}
+
+/// Opens RTA websockets, fetching the XSTS token from `XBLAuth` on every attempt
+/// so that a reconnect never reuses an expired one.
+#[derive(Debug, Clone)]
+pub(crate) struct Connector {
+    xbl_auth: Arc<Mutex<XBLAuth>>,
+    user_name: String,
+    uri: String,
+    client: Client,
+    token_refresh_margin: Duration,
+    recorder: Option<Recorder>,
+    transport: Arc<dyn RtaTransport>,
+}
+
+pub(crate) struct Connection {
+    pub(crate) writer: FrameSink,
+    pub(crate) reader: FrameStream,
+    /// When to replace the connection, ahead of its token expiring.
+    pub(crate) refresh_at: Instant,
+}
+
+impl Connector {
+    #[inline]
+    pub(crate) fn user_name(&self) -> &str {
+        &self.user_name
+    }
+
+    async fn authorization(&self) -> Result<String> {
+        let xsts = self.xbl_auth.lock().await.get_xbox_token().await?.take();
+        Ok(format!("XBL3.0 x={};{}", xsts.user_hash, xsts.token))
+    }
+
+    // The request carries the token, so only the uri is recorded.
+    #[instrument(name = "rta_connect", skip_all, fields(uri = %self.uri, user = %self.user_name))]
+    pub(crate) async fn connect(&self) -> Result<Connection> {
+        // Long enough that the next refresh is not due right away.
+        let valid_for = 2 * self.token_refresh_margin.as_secs();
+        let xsts = {
+            let mut xbl_auth = self.xbl_auth.lock().await;
+            xbl_auth.get_xbox_token_valid_for(valid_for).await?
+        };
+        let lifetime = Duration::from_secs(xsts.expired_at().saturating_sub(now_secs!()));
+        // Otherwise a short-lived token would be due for a refresh right away,
+        // and so would every token replacing it.
+        let refresh_in = lifetime - self.token_refresh_margin.min(lifetime / 2);
+        let refresh_at = Instant::now() + refresh_in;
+        let authorization = format!("XBL3.0 x={};{}", xsts.user_hash, xsts.token);
+        let request = ClientRequestBuilder::new(self.uri.parse()?)
+            .with_header("authorization", &authorization)
+            .with_sub_protocol("rta.xboxlive.com.V2")
+            .into_client_request()?;
+        let (mut writer, mut reader) = self.transport.connect(request).await?;
+        if let Some(recorder) = &self.recorder {
+            writer = writer.record(recorder.clone());
+            reader = reader.record(recorder.clone());
+        }
+        info!(?refresh_in, "connected");
+        Ok(Connection {
+            writer,
+            reader,
+            refresh_at,
+        })
+    }
+
+    /// Reads the current state of a subscribed resource.
+    pub(crate) async fn fetch(&self, uri: &str) -> Result<Value> {
+        debug!(uri, "fetching resource");
+        let state = self
+            .client
+            .get(uri)
+            .header("authorization", self.authorization().await?)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(state)
+    }
 }
@@ -0,0 +1,554 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+use futures_util::{Stream, StreamExt};
+use tokio::sync::Notify;
+
+use serde::Deserialize;
+
+use crate::{event::RtaEvent, message::EventData, resource::ResourceKind};
+
+/// Selects the events an [`EventStream`] receives.
+///
+/// Events of a subscription must match every restriction set. Connection-wide
+/// events (pongs, disconnects, resyncs, ...) are delivered unless excluded with
+/// [`EventFilter::without_connection_events`].
+#[derive(Debug, Clone)]
+pub struct EventFilter {
+    subscriptions: Option<HashSet<u64>>,
+    resources: Option<HashSet<ResourceKind>>,
+    connection_events: bool,
+}
+
+impl Default for EventFilter {
+    fn default() -> Self {
+        Self {
+            subscriptions: None,
+            resources: None,
+            connection_events: true,
+        }
+    }
+}
+
+impl EventFilter {
+    /// Only events of the subscription with this `Subscription::key`.
+    pub fn subscription(mut self, key: u64) -> Self {
+        self.subscriptions
+            .get_or_insert_with(HashSet::new)
+            .insert(key);
+        self
+    }
+
+    /// Only events of subscriptions to this kind of resource.
+    pub fn resource(mut self, kind: ResourceKind) -> Self {
+        self.resources.get_or_insert_with(HashSet::new).insert(kind);
+        self
+    }
+
+    pub fn without_connection_events(mut self) -> Self {
+        self.connection_events = false;
+        self
+    }
+
+    fn matches(&self, source: Option<&EventSource>) -> bool {
+        let Some(source) = source else {
+            return self.connection_events;
+        };
+        self.subscriptions
+            .as_ref()
+            .is_none_or(|keys| keys.contains(&source.key))
+            && self
+                .resources
+                .as_ref()
+                .is_none_or(|kinds| source.kind.is_some_and(|kind| kinds.contains(&kind)))
+    }
+}
+
+/// The subscription an event belongs to.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct EventSource {
+    pub(crate) key: u64,
+    pub(crate) kind: Option<ResourceKind>,
+}
+
+/// What a stream's queue does with new events once it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverflowPolicy {
+    /// Wait until the consumer catches up, holding up the connection and every
+    /// other stream meanwhile.
+    Block,
+    /// Make room by dropping the oldest queued event.
+    #[default]
+    DropOldest,
+    /// Drop the new event.
+    DropNewest,
+    /// Fold shoulder taps into a queued tap for the same resource, keeping the
+    /// highest `ChangeNumber`, and drop the oldest event for anything else.
+    Coalesce,
+}
+
+#[derive(Debug)]
+struct Consumer {
+    filter: EventFilter,
+    policy: OverflowPolicy,
+    queue: VecDeque<RtaEvent>,
+    // Events dropped since the consumer last read.
+    lagged: u64,
+    dropped: u64,
+    waker: Option<Waker>,
+}
+
+impl Consumer {
+    /// Queues `event`, returning whether one was dropped to do so.
+    fn push(&mut self, event: RtaEvent, capacity: usize) -> bool {
+        let event = match self.policy {
+            OverflowPolicy::Coalesce if self.queue.len() >= capacity => {
+                match coalesce(&mut self.queue, event) {
+                    Some(event) => event,
+                    None => return false,
+                }
+            }
+            _ => event,
+        };
+        let mut dropped = false;
+        if self.queue.len() >= capacity {
+            dropped = true;
+            self.lagged += 1;
+            self.dropped += 1;
+            if self.policy == OverflowPolicy::DropNewest {
+                return dropped;
+            }
+            self.queue.pop_front();
+        }
+        self.queue.push_back(event);
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+        dropped
+    }
+}
+
+/// Merges the shoulder taps of `event` into queued events of the same
+/// subscription, returning what is left of it. Events of resources other than
+/// sessions carry no shoulder taps and are kept as they are.
+fn coalesce(queue: &mut VecDeque<RtaEvent>, event: RtaEvent) -> Option<RtaEvent> {
+    let RtaEvent::Event { sub_id, data } = &event else {
+        return Some(event);
+    };
+    let Ok(mut incoming) = EventData::deserialize(data) else {
+        return Some(event);
+    };
+    // Nothing to merge.
+    if incoming.shoulder_taps.is_empty() {
+        return Some(event);
+    }
+    let (sub_id, taps) = (*sub_id, incoming.shoulder_taps.len());
+    for queued in queue.iter_mut() {
+        let RtaEvent::Event {
+            sub_id: queued_sub_id,
+            data: queued_data,
+        } = queued
+        else {
+            continue;
+        };
+        if *queued_sub_id != sub_id {
+            continue;
+        }
+        let Ok(mut merged) = EventData::deserialize(&*queued_data) else {
+            continue;
+        };
+        let mut changed = false;
+        incoming.shoulder_taps.retain(|tap| {
+            let Some(queued_tap) = merged
+                .shoulder_taps
+                .iter_mut()
+                .find(|queued_tap| queued_tap.resource == tap.resource)
+            else {
+                return true;
+            };
+            if tap.change_number > queued_tap.change_number {
+                *queued_tap = tap.clone();
+                changed = true;
+            }
+            false
+        });
+        if changed {
+            if let Ok(merged) = serde_json::to_value(&merged) {
+                *queued_data = merged;
+            }
+        }
+        if incoming.shoulder_taps.is_empty() {
+            return None;
+        }
+    }
+    if incoming.shoulder_taps.len() == taps {
+        return Some(event);
+    }
+    match serde_json::to_value(&incoming) {
+        Ok(data) => Some(RtaEvent::Event { sub_id, data }),
+        Err(_) => Some(event),
+    }
+}
+
+#[derive(Debug, Default)]
+struct Inner {
+    next_id: u64,
+    consumers: HashMap<u64, Consumer>,
+    // Connections publishing to the bus.
+    publishers: usize,
+    closed: bool,
+    dropped: u64,
+    next_key: u64,
+}
+
+/// Fans the events of one or more connections out to any number of
+/// [`EventStream`]s. It also hands out the subscription keys, so that they are
+/// unique across the connections sharing it.
+///
+/// Every stream has its own queue of `capacity` events and handles a full queue
+/// according to its [`OverflowPolicy`]. Streams that lose events are told how
+/// many with [`RtaEvent::Lagged`].
+#[derive(Debug, Clone)]
+pub(crate) struct EventBus {
+    inner: Arc<Mutex<Inner>>,
+    // Signalled whenever a queue shrinks, for blocked publishers.
+    space: Arc<Notify>,
+    capacity: usize,
+    policy: OverflowPolicy,
+}
+
+impl EventBus {
+    pub(crate) fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            inner: Default::default(),
+            space: Default::default(),
+            capacity: capacity.max(1),
+            policy,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn subscribe(&self, filter: EventFilter) -> EventStream {
+        self.subscribe_with_policy(filter, self.policy)
+    }
+
+    pub(crate) fn subscribe_with_policy(
+        &self,
+        filter: EventFilter,
+        policy: OverflowPolicy,
+    ) -> EventStream {
+        let mut inner = self.inner.lock().unwrap();
+        let id = inner.next_id;
+        inner.next_id += 1;
+        let consumer = Consumer {
+            filter,
+            policy,
+            queue: VecDeque::new(),
+            lagged: 0,
+            dropped: 0,
+            waker: None,
+        };
+        inner.consumers.insert(id, consumer);
+        EventStream {
+            id,
+            bus: self.clone(),
+        }
+    }
+
+    pub(crate) async fn publish(&self, source: Option<EventSource>, event: RtaEvent) {
+        loop {
+            let space = self.space.notified();
+            tokio::pin!(space);
+            space.as_mut().enable();
+            {
+                let mut inner = self.inner.lock().unwrap();
+                let blocked = inner.consumers.values().any(|consumer| {
+                    consumer.policy == OverflowPolicy::Block
+                        && consumer.queue.len() >= self.capacity
+                        && consumer.filter.matches(source.as_ref())
+                });
+                if !blocked || inner.closed {
+                    let mut dropped = 0;
+                    for consumer in inner.consumers.values_mut() {
+                        if consumer.filter.matches(source.as_ref())
+                            && consumer.push(event.clone(), self.capacity)
+                        {
+                            dropped += 1;
+                        }
+                    }
+                    inner.dropped += dropped;
+                    return;
+                }
+            }
+            space.await;
+        }
+    }
+
+    /// Events dropped across all streams so far.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.inner.lock().unwrap().dropped
+    }
+
+    pub(crate) fn next_key(&self) -> u64 {
+        let mut inner = self.inner.lock().unwrap();
+        inner.next_key += 1;
+        inner.next_key - 1
+    }
+
+    #[inline]
+    pub(crate) fn attach(&self) {
+        self.inner.lock().unwrap().publishers += 1;
+    }
+
+    /// Once the last connection detaches, every stream ends after draining its
+    /// queue.
+    pub(crate) fn detach(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.publishers = inner.publishers.saturating_sub(1);
+        if inner.publishers > 0 {
+            return;
+        }
+        inner.closed = true;
+        for consumer in inner.consumers.values_mut() {
+            if let Some(waker) = consumer.waker.take() {
+                waker.wake();
+            }
+        }
+        self.space.notify_waiters();
+    }
+}
+
+/// Events of an RTA connection, as selected by an [`EventFilter`].
+#[derive(Debug)]
+pub struct EventStream {
+    id: u64,
+    bus: EventBus,
+}
+
+impl EventStream {
+    #[inline]
+    pub async fn recv(&mut self) -> Option<RtaEvent> {
+        self.next().await
+    }
+
+    /// Events this stream lost to its [`OverflowPolicy`] so far.
+    pub fn dropped(&self) -> u64 {
+        let inner = self.bus.inner.lock().unwrap();
+        inner
+            .consumers
+            .get(&self.id)
+            .map_or(0, |consumer| consumer.dropped)
+    }
+}
+
+impl Stream for EventStream {
+    type Item = RtaEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut inner = self.bus.inner.lock().unwrap();
+        let closed = inner.closed;
+        let Some(consumer) = inner.consumers.get_mut(&self.id) else {
+            return Poll::Ready(None);
+        };
+        if consumer.lagged > 0 {
+            let missed = std::mem::take(&mut consumer.lagged);
+            return Poll::Ready(Some(RtaEvent::Lagged { missed }));
+        }
+        match consumer.queue.pop_front() {
+            Some(event) => {
+                self.bus.space.notify_waiters();
+                Poll::Ready(Some(event))
+            }
+            None if closed => Poll::Ready(None),
+            None => {
+                consumer.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for EventStream {
+    fn drop(&mut self) {
+        if let Ok(mut inner) = self.bus.inner.lock() {
+            inner.consumers.remove(&self.id);
+        }
+        self.bus.space.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use super::{EventBus, EventFilter, EventSource, OverflowPolicy};
+    use crate::{event::RtaEvent, message::EventData, resource::ResourceKind};
+
+    fn event(sub_id: i64) -> RtaEvent {
+        RtaEvent::Event {
+            sub_id,
+            data: json!({ "Ncid": "", "ShoulderTaps": [] }),
+        }
+    }
+
+    fn sub_id(event: Option<RtaEvent>) -> Option<i64> {
+        match event? {
+            RtaEvent::Event { sub_id, .. } => Some(sub_id),
+            _ => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn fans_out_and_filters() {
+        let bus = EventBus::new(8, OverflowPolicy::DropOldest);
+        let mut all = bus.subscribe(EventFilter::default());
+        let social = bus.subscribe(
+            EventFilter::default()
+                .resource(ResourceKind::Social)
+                .without_connection_events(),
+        );
+        let mut first = bus.subscribe(EventFilter::default().subscription(0));
+        bus.attach();
+
+        let presence = EventSource {
+            key: 0,
+            kind: Some(ResourceKind::Presence),
+        };
+        let friends = EventSource {
+            key: 1,
+            kind: Some(ResourceKind::Social),
+        };
+        bus.publish(Some(presence), event(10)).await;
+        bus.publish(Some(friends), event(11)).await;
+        bus.publish(None, RtaEvent::Resync).await;
+        bus.detach();
+
+        assert_eq!(sub_id(all.recv().await), Some(10));
+        assert_eq!(sub_id(all.recv().await), Some(11));
+        assert!(matches!(all.recv().await, Some(RtaEvent::Resync)));
+        assert!(all.recv().await.is_none());
+        assert_eq!(
+            social
+                .map(|event| sub_id(Some(event)))
+                .collect::<Vec<_>>()
+                .await,
+            vec![Some(11)]
+        );
+        assert_eq!(sub_id(first.recv().await), Some(10));
+        assert!(matches!(first.recv().await, Some(RtaEvent::Resync)));
+    }
+
+    #[tokio::test]
+    async fn reports_lag() {
+        let bus = EventBus::new(2, OverflowPolicy::DropOldest);
+        let mut slow = bus.subscribe(EventFilter::default());
+        for sub_id in 0..5 {
+            bus.publish(None, event(sub_id)).await;
+        }
+        assert!(matches!(
+            slow.recv().await,
+            Some(RtaEvent::Lagged { missed: 3 })
+        ));
+        assert_eq!(sub_id(slow.recv().await), Some(3));
+        assert_eq!(sub_id(slow.recv().await), Some(4));
+    }
+
+    #[tokio::test]
+    async fn drops_newest() {
+        let bus = EventBus::new(2, OverflowPolicy::DropNewest);
+        let mut slow = bus.subscribe(EventFilter::default());
+        for sub_id in 0..5 {
+            bus.publish(None, event(sub_id)).await;
+        }
+        assert_eq!(slow.dropped(), 3);
+        assert_eq!(bus.dropped(), 3);
+        assert!(matches!(
+            slow.recv().await,
+            Some(RtaEvent::Lagged { missed: 3 })
+        ));
+        assert_eq!(sub_id(slow.recv().await), Some(0));
+        assert_eq!(sub_id(slow.recv().await), Some(1));
+    }
+
+    #[tokio::test]
+    async fn blocks_until_read() {
+        let bus = EventBus::new(1, OverflowPolicy::Block);
+        let mut slow = bus.subscribe(EventFilter::default());
+        bus.publish(None, event(0)).await;
+        let publisher = bus.clone();
+        let blocked = tokio::spawn(async move { publisher.publish(None, event(1)).await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!blocked.is_finished());
+
+        assert_eq!(sub_id(slow.recv().await), Some(0));
+        blocked.await.unwrap();
+        assert_eq!(sub_id(slow.recv().await), Some(1));
+        assert_eq!(slow.dropped(), 0);
+    }
+
+    #[tokio::test]
+    async fn coalesces_shoulder_taps() {
+        fn taps(taps: &[(&str, i64)]) -> RtaEvent {
+            let taps: Vec<_> = taps
+                .iter()
+                .map(|(resource, change_number)| {
+                    json!({
+                        "Timestamp": "2024-08-20T12:34:56.789Z",
+                        "Subscription": "0a0b0c0d",
+                        "ResourceType": "MultiplayerSessionDirectory",
+                        "Resource": resource,
+                        "Branch": "b8e3c2a0",
+                        "ChangeNumber": change_number
+                    })
+                })
+                .collect();
+            RtaEvent::Event {
+                sub_id: 1,
+                data: json!({ "Ncid": "fd4b0d4e", "ShoulderTaps": taps }),
+            }
+        }
+        fn change_numbers(event: Option<RtaEvent>) -> Vec<(String, i64)> {
+            let Some(RtaEvent::Event { data, .. }) = event else {
+                panic!("expected an event");
+            };
+            serde_json::from_value::<EventData>(data)
+                .unwrap()
+                .shoulder_taps
+                .into_iter()
+                .map(|tap| (tap.resource, tap.change_number))
+                .collect()
+        }
+
+        let bus = EventBus::new(2, OverflowPolicy::Coalesce);
+        let mut slow = bus.subscribe(EventFilter::default());
+        bus.attach();
+        // Queued as they are while there is room.
+        bus.publish(None, taps(&[("a~t~1", 1)])).await;
+        bus.publish(None, taps(&[("a~t~1", 3), ("a~t~2", 1)])).await;
+        // Then folded into the queued taps.
+        bus.publish(None, taps(&[("a~t~1", 2), ("a~t~2", 4)])).await;
+        // What cannot be folded makes room by dropping the oldest event.
+        bus.publish(None, taps(&[("a~t~3", 1)])).await;
+        bus.detach();
+
+        assert!(matches!(
+            slow.recv().await,
+            Some(RtaEvent::Lagged { missed: 1 })
+        ));
+        assert_eq!(
+            change_numbers(slow.recv().await),
+            vec![("a~t~1".into(), 3), ("a~t~2".into(), 4)]
+        );
+        assert_eq!(change_numbers(slow.recv().await), vec![("a~t~3".into(), 1)]);
+        assert!(slow.recv().await.is_none());
+        assert_eq!(slow.dropped(), 1);
+    }
+}
@@ -0,0 +1,56 @@
+use std::fmt;
+
+use tokio_tungstenite::tungstenite;
+
+use crate::status::Status;
+
+#[derive(Debug)]
+pub enum RtaError {
+    /// RTA answered the request with a failure status.
+    Status(Status),
+    /// No answer arrived within the request timeout.
+    Timeout,
+    /// The client stopped before the request was answered.
+    Closed,
+    WebSocket(tungstenite::Error),
+    /// The request could not be encoded.
+    Encode(serde_json::Error),
+    /// No connection could be opened for the request.
+    Connect(anyhow::Error),
+}
+
+impl fmt::Display for RtaError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Status(status) => write!(f, "RTA request failed: {status:?}"),
+            Self::Timeout => f.write_str("RTA request timed out"),
+            Self::Closed => f.write_str("RTA client closed"),
+            Self::WebSocket(e) => write!(f, "WebSocket error: {e}"),
+            Self::Encode(e) => write!(f, "Failed to encode RTA request: {e}"),
+            Self::Connect(e) => write!(f, "Failed to connect to RTA: {e:#}"),
+        }
+    }
+}
+
+impl std::error::Error for RtaError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::WebSocket(e) => Some(e),
+            Self::Encode(e) => Some(e),
+            Self::Connect(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<tungstenite::Error> for RtaError {
+    fn from(value: tungstenite::Error) -> Self {
+        Self::WebSocket(value)
+    }
+}
+
+impl From<serde_json::Error> for RtaError {
+    fn from(value: serde_json::Error) -> Self {
+        Self::Encode(value)
+    }
+}
@@ -1,18 +1,74 @@
-use crate::message::EventData;
+use std::time::Duration;
 
-#[derive(Debug)]
+use serde_json::Value;
+
+use crate::status::Status;
+
+#[derive(Debug, Clone)]
 pub enum RtaEvent {
     Subscribe {
         seq_id: i64,
         sub_id: i64,
-        connection_id: String,
+        payload: Value,
+    },
+    /// A subscription nobody is awaiting was refused, e.g. one replayed after a
+    /// reconnect.
+    SubscribeFailed {
+        seq_id: i64,
+        uri: String,
+        status: Status,
+    },
+    /// RTA was throttling or unavailable; the subscription is requested again
+    /// after `delay`.
+    SubscribeRetrying {
+        uri: String,
+        status: Status,
+        attempt: u32,
+        delay: Duration,
     },
     Unsubscribe {
         seq_id: i64,
+        sub_id: i64,
+        uri: String,
+    },
+    /// `data` decodes with [`RtaResource::decode_event`] for the resource
+    /// `sub_id` subscribes to.
+    ///
+    /// [`RtaResource::decode_event`]: crate::resource::RtaResource::decode_event
+    Event { sub_id: i64, data: Value },
+    /// `latency` is the round-trip time when the pong answers one of the
+    /// client's keepalive pings.
+    Pong {
+        payload: Vec<u8>,
+        latency: Option<Duration>,
+    },
+    /// A frame that could not be decoded, or a message type this client does
+    /// not know. The connection carries on.
+    Malformed { frame: String, error: String },
+    /// RTA may have dropped events; subscribed resources should be re-read.
+    Resync,
+    /// State of a resource re-read by its subscription's resync hook.
+    Resynced {
+        sub_id: i64,
+        uri: String,
+        state: Value,
     },
-    Event {
+    ResyncFailed {
         sub_id: i64,
-        data: EventData,
+        uri: String,
+        error: String,
     },
-    Pong(Vec<u8>),
+    /// The consumer fell behind and lost its `missed` oldest events.
+    Lagged { missed: u64 },
+    /// The connection dropped; the client is about to reconnect.
+    Disconnected { reason: String },
+    /// A new connection is open and every subscription is being replayed, so
+    /// the `sub_id`s of later `Subscribe` events replace the previous ones.
+    Reconnected { attempts: u32 },
+    /// The connection was replaced by one with a fresh XSTS token. As after
+    /// `Reconnected`, every subscription is replayed and gets a new `sub_id`.
+    Refreshed,
+    /// The connections subscription reported a new `ConnectionId`, so session
+    /// memberships bound to the previous one must be registered again.
+    ConnectionIdChanged { connection_id: String },
 }
@@ -0,0 +1,95 @@
+use std::{fmt, ops::Deref, time::Duration};
+
+use futures_util::future::join_all;
+use tokio::task::JoinHandle;
+use tracing::warn;
+
+use crate::WSWriter;
+
+/// Why an RTA client stopped.
+#[derive(Debug)]
+pub enum Termination {
+    /// The connection was closed on request and the server confirmed it.
+    Closed,
+    /// The server did not confirm the close in time, so the client was stopped.
+    Timeout,
+    /// The client gave up, e.g. after running out of reconnect attempts.
+    Error(anyhow::Error),
+}
+
+impl fmt::Display for Termination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Closed => f.write_str("RTA connection closed"),
+            Self::Timeout => f.write_str("RTA connection did not close in time"),
+            Self::Error(e) => write!(f, "RTA client failed: {e:?}"),
+        }
+    }
+}
+
+/// The task reading an RTA connection, returned by `RtaClient::listen`.
+///
+/// Dereferences to the connection's [`WSWriter`]. Dropping the handle leaves
+/// the task running.
+#[derive(Debug)]
+pub struct RtaHandle {
+    writer: WSWriter,
+    task: JoinHandle<Termination>,
+}
+
+impl RtaHandle {
+    pub(crate) fn new(writer: WSWriter, task: JoinHandle<Termination>) -> Self {
+        Self { writer, task }
+    }
+
+    #[inline]
+    pub fn writer(&self) -> &WSWriter {
+        &self.writer
+    }
+
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    /// Waits for the client to stop on its own.
+    pub async fn join(self) -> Termination {
+        match self.task.await {
+            Ok(termination) => termination,
+            Err(e) => Termination::Error(e.into()),
+        }
+    }
+
+    /// Unsubscribes everything, closes the connection and waits up to `timeout`
+    /// for the server to confirm before stopping the client regardless.
+    pub async fn shutdown(self, timeout: Duration) -> Termination {
+        let Self { writer, mut task } = self;
+        let deadline = tokio::time::Instant::now() + timeout;
+        let unsubscribes = writer
+            .subscriptions()
+            .into_iter()
+            .map(|subscription| writer.unsubscribe_key(subscription.key));
+        // Closing matters more than confirming every unsubscribe.
+        let _ = tokio::time::timeout_at(deadline, join_all(unsubscribes)).await;
+        if let Err(e) = writer.close().await {
+            warn!(error = format!("{e:#}"), "close failed");
+        }
+        match tokio::time::timeout_at(deadline, &mut task).await {
+            Ok(Ok(termination)) => termination,
+            Ok(Err(e)) => Termination::Error(e.into()),
+            Err(_) => {
+                warn!(?timeout, "close timed out");
+                task.abort();
+                Termination::Timeout
+            }
+        }
+    }
+}
+
+impl Deref for RtaHandle {
+    type Target = WSWriter;
+
+    fn deref(&self) -> &Self::Target {
+        &self.writer
+    }
+}
@@ -0,0 +1,67 @@
+use std::{collections::VecDeque, time::Duration};
+
+use tokio::time::Instant;
+
+/// Tracks the pings sent on one connection and the pongs answering them.
+#[derive(Debug)]
+pub(crate) struct Keepalive {
+    max_missed_pongs: u32,
+    next_id: u64,
+    // Pings without a pong yet, oldest first.
+    outstanding: VecDeque<(u64, Instant)>,
+}
+
+impl Keepalive {
+    pub(crate) fn new(max_missed_pongs: u32) -> Self {
+        Self {
+            max_missed_pongs: max_missed_pongs.max(1),
+            next_id: 0,
+            outstanding: VecDeque::new(),
+        }
+    }
+
+    /// Payload of the next ping, or `None` once too many pongs were missed
+    /// and the connection should be treated as dead.
+    pub(crate) fn ping(&mut self) -> Option<Vec<u8>> {
+        if self.missed() >= self.max_missed_pongs {
+            return None;
+        }
+        let id = self.next_id;
+        self.next_id += 1;
+        self.outstanding.push_back((id, Instant::now()));
+        Some(id.to_be_bytes().to_vec())
+    }
+
+    /// Round-trip time of the ping `payload` answers, if it answers one. Older
+    /// pings are considered answered too.
+    pub(crate) fn pong(&mut self, payload: &[u8]) -> Option<Duration> {
+        let id = u64::from_be_bytes(payload.try_into().ok()?);
+        let position = self.outstanding.iter().position(|(sent, _)| *sent == id)?;
+        let (_, sent_at) = self.outstanding.drain(..=position).next_back()?;
+        Some(sent_at.elapsed())
+    }
+
+    #[inline]
+    pub(crate) fn missed(&self) -> u32 {
+        self.outstanding.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Keepalive;
+
+    #[test]
+    fn gives_up_after_missed_pongs() {
+        let mut keepalive = Keepalive::new(2);
+        let first = keepalive.ping().unwrap();
+        let second = keepalive.ping().unwrap();
+        assert!(keepalive.ping().is_none());
+
+        assert!(keepalive.pong(b"unsolicited").is_none());
+        assert!(keepalive.pong(&second).is_some());
+        assert_eq!(keepalive.missed(), 0);
+        assert!(keepalive.pong(&first).is_none());
+        assert!(keepalive.ping().is_some());
+    }
+}
@@ -1,192 +1,1021 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, MutexGuard,
+    },
+    time::Duration,
+};
 
+use backoff::Backoff;
+use builder::{Connection, Connector};
+use bus::{EventBus, EventFilter, EventStream, OverflowPolicy};
+use error::RtaError;
 use event::RtaEvent;
-use message::{MessageData, MessageType};
+use handle::{RtaHandle, Termination};
+use keepalive::Keepalive;
+use message::{MessageData, Request};
 
 use anyhow::{bail, Result};
-use futures_util::{
-    stream::{SplitSink, SplitStream},
-    SinkExt, StreamExt,
-};
+use futures_util::{SinkExt, StreamExt};
+use registry::{Entry, Registry, SubscribeWaiter, UnsubscribeWaiter, Unsubscribing};
+use resource::{ConnectionInfo, ResourceKind, RtaResource};
+use resync::ResyncHook;
+use serde::Deserialize;
 use status::Status;
+use subscription::{ActiveSubscription, Subscription};
 use tokio::{
-    net::TcpStream,
-    sync::{
-        mpsc::{Receiver, Sender},
-        Mutex,
-    },
+    sync::{mpsc, oneshot, watch, Mutex},
+    time::{Instant, MissedTickBehavior},
 };
-use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, info, info_span, trace, warn, Instrument};
+use transport::{FrameSink, FrameStream};
 
+pub mod backoff;
 pub mod builder;
+pub mod bus;
+pub mod error;
 pub mod event;
+pub mod handle;
+mod keepalive;
 pub mod message;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
+pub mod pool;
+pub mod record;
+mod registry;
+pub mod resource;
+pub mod resync;
 pub mod status;
+pub mod subscription;
+pub mod transport;
+
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(0);
 
 #[derive(Debug)]
 pub struct RtaClient {
     subscription_urls: Vec<String>,
+    // `None` when replaying a recording.
+    connector: Option<Connector>,
+    reconnect_backoff: Backoff,
+    ping_interval: Duration,
+    max_missed_pongs: u32,
     ws_writer: WSWriter,
-    ws_reader: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    rta_writer: Sender<RtaEvent>,
-    rta_reader: Receiver<RtaEvent>,
+    ws_reader: FrameStream,
+    refresh_at: Option<Instant>,
+    subscribe_backoff: Backoff,
+    overflow: Option<mpsc::UnboundedSender<Overflowed>>,
 }
 
 impl RtaClient {
-    pub fn listen(self) -> Result<(WSWriter, Receiver<RtaEvent>)> {
+    /// Starts reading the connection. The returned stream receives every event;
+    /// more consumers can be added with [`WSWriter::events`].
+    pub fn listen(self) -> Result<(RtaHandle, EventStream)> {
         let Self {
             subscription_urls,
+            connector,
+            reconnect_backoff,
+            ping_interval,
+            max_missed_pongs,
             ws_writer,
             ws_reader,
-            rta_writer,
-            rta_reader,
+            refresh_at,
+            subscribe_backoff,
+            overflow,
         } = self;
         let ws_writer_c = ws_writer.clone();
-        let mut stream = RtaStream::new(subscription_urls, ws_writer, ws_reader, rta_writer);
-        tokio::spawn(async move {
-            if let Err(e) = stream.run().await {
-                println!("RtaClientError: \"{e:?}\".");
-            }
-        });
-        Ok((ws_writer_c, rta_reader))
+        let events = ws_writer.events(EventFilter::default());
+        let bus = ws_writer.shared.bus.clone();
+        bus.attach();
+        let mut stream = RtaStream::new(subscription_urls, ws_writer, ws_reader)
+            .set_keepalive(ping_interval, max_missed_pongs)
+            .set_refresh_at(refresh_at)
+            .set_subscribe_backoff(subscribe_backoff)
+            .set_overflow(overflow);
+        let span = info_span!(
+            "rta_client",
+            client = NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            user = connector.as_ref().map(Connector::user_name),
+        );
+        if let Some(connector) = connector {
+            stream = stream.set_reconnect(connector, reconnect_backoff);
+        }
+        let run = async move {
+            let termination = match stream.run().await {
+                Ok(()) => {
+                    info!("closed");
+                    Termination::Closed
+                }
+                Err(e) => {
+                    warn!(error = format!("{e:#}"), "stopped");
+                    stream.hand_over();
+                    Termination::Error(e)
+                }
+            };
+            bus.detach();
+            termination
+        };
+        let task = tokio::spawn(run.instrument(span));
+        Ok((RtaHandle::new(ws_writer_c, task), events))
+    }
+
+    /// Hands replayed subscriptions refused with `SubscriptionLimitReached` to
+    /// `overflow` instead of dropping them, as well as every subscription left
+    /// when the client gives up.
+    pub(crate) fn set_overflow(mut self, overflow: mpsc::UnboundedSender<Overflowed>) -> Self {
+        self.overflow = Some(overflow);
+        self
     }
 }
 
+/// A subscription that no longer fits on the connection `from`, or outlived it.
+#[derive(Debug)]
+pub(crate) struct Overflowed {
+    pub(crate) seq_id: i64,
+    pub(crate) key: u64,
+    pub(crate) uri: String,
+    pub(crate) from: WSWriter,
+}
+
+#[derive(Debug)]
+struct Shared {
+    sequence_id: AtomicI64,
+    writer: Mutex<FrameSink>,
+    registry: std::sync::Mutex<Registry>,
+    closed: watch::Sender<bool>,
+    latency: std::sync::Mutex<Option<Duration>>,
+    request_timeout: Duration,
+    bus: EventBus,
+    connection_id: watch::Sender<Option<String>>,
+    // Subscriptions moved to another connection, by key.
+    moved: std::sync::Mutex<HashMap<u64, WSWriter>>,
+}
+
+/// Sending half of an RTA connection; clones share the connection, its sequence
+/// ids and its subscriptions.
 #[derive(Debug, Clone)]
 pub struct WSWriter {
-    sequence_id: i64,
-    writer: Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>,
+    shared: Arc<Shared>,
 }
 impl WSWriter {
-    pub fn new(
-        writer: Arc<Mutex<SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>>>,
-    ) -> Self {
+    pub(crate) fn new(writer: FrameSink, request_timeout: Duration, bus: EventBus) -> Self {
+        let shared = Shared {
+            sequence_id: AtomicI64::new(0),
+            writer: Mutex::new(writer),
+            registry: Default::default(),
+            closed: watch::Sender::new(false),
+            latency: Default::default(),
+            request_timeout,
+            bus,
+            connection_id: watch::Sender::new(None),
+            moved: Default::default(),
+        };
         Self {
-            sequence_id: 0,
-            writer,
+            shared: Arc::new(shared),
         }
     }
 
-    pub async fn subscribe(&mut self, uri: &str) -> Result<()> {
-        self.send(Message::Text(format!(
-            r#"[{},{},"{}"]"#,
-            MessageType::Subscribe as u8,
-            self.sequence_id,
-            uri
-        )))
-        .await?;
-        Ok(())
+    /// Subscribes to `uri` and waits for RTA to accept it.
+    pub async fn subscribe(&self, uri: &str) -> Result<Subscription, RtaError> {
+        self.subscribe_inner(uri, None).await
+    }
+    #[inline]
+    pub async fn subscribe_resource(
+        &self,
+        resource: &RtaResource,
+    ) -> Result<Subscription, RtaError> {
+        self.subscribe(&resource.uri()).await
+    }
+    /// Like [`WSWriter::subscribe`], re-reading the resource with `resync`
+    /// whenever RTA asks for a resync. The outcome arrives on the subscription
+    /// as `RtaEvent::Resynced` or `RtaEvent::ResyncFailed`.
+    pub async fn subscribe_with_resync(
+        &self,
+        uri: &str,
+        resync: ResyncHook,
+    ) -> Result<Subscription, RtaError> {
+        self.subscribe_inner(uri, Some(resync)).await
+    }
+    async fn subscribe_inner(
+        &self,
+        uri: &str,
+        resync: Option<ResyncHook>,
+    ) -> Result<Subscription, RtaError> {
+        let (waiter, rx) = SubscribeWaiter::new(self.shared.request_timeout);
+        let key = self.shared.bus.next_key();
+        self.registry().insert(key, uri.to_owned(), resync);
+        let filter = EventFilter::default()
+            .subscription(key)
+            .without_connection_events();
+        let events = self.events(filter);
+        let response = match self.request_subscribe(key, uri, Some(waiter)).await {
+            Ok(()) => self.wait(rx).await,
+            Err(e) => Err(e),
+        };
+        match response {
+            Ok(payload) => Ok(Subscription::new(
+                key,
+                uri.to_owned(),
+                payload,
+                events,
+                self.clone(),
+            )),
+            Err(e) => {
+                self.registry().remove(key);
+                Err(e)
+            }
+        }
+    }
+    pub(crate) async fn request_subscribe(
+        &self,
+        key: u64,
+        uri: &str,
+        waiter: Option<SubscribeWaiter>,
+    ) -> Result<(), RtaError> {
+        let request = self.register_subscribe(key, uri, waiter)?;
+        self.send_message(Message::Text(request)).await
+    }
+    /// Registers a subscribe request under the next sequence id and returns
+    /// the frame to send for it.
+    fn register_subscribe(
+        &self,
+        key: u64,
+        uri: &str,
+        waiter: Option<SubscribeWaiter>,
+    ) -> serde_json::Result<String> {
+        let seq_id = self.next_sequence_id();
+        debug!(seq_id, key, uri, "subscribe");
+        let request = Request::Subscribe {
+            seq_id,
+            uri: uri.to_owned(),
+        }
+        .encode()?;
+        self.registry().request(seq_id, key, waiter);
+        Ok(request)
     }
-    pub async fn unsubscribe(&mut self, subscription_id: i64) -> Result<()> {
-        self.send(Message::Text(format!(
-            r#"[{},{},{}]"#,
-            MessageType::Subscribe as u8,
-            self.sequence_id,
-            subscription_id
-        )))
-        .await?;
+    pub async fn unsubscribe(&self, subscription_id: i64) -> Result<()> {
+        let uri = {
+            let mut registry = self.registry();
+            let key = registry.key_of(subscription_id);
+            key.and_then(|key| registry.remove(key))
+                .map(|entry| entry.uri)
+                .unwrap_or_default()
+        };
+        self.request_unsubscribe(subscription_id, uri, None).await?;
         Ok(())
     }
+    /// Unsubscribes the subscription registered under `key` and waits for RTA
+    /// to confirm.
+    pub(crate) async fn unsubscribe_key(&self, key: u64) -> Result<(), RtaError> {
+        let moved = self.shared.moved.lock().unwrap().remove(&key);
+        if let Some(owner) = moved {
+            return Box::pin(owner.unsubscribe_key(key)).await;
+        }
+        let Some(Entry {
+            uri,
+            sub_id: Some(sub_id),
+            ..
+        }) = self.registry().remove(key)
+        else {
+            // Not live on the current connection, so there is nothing to undo.
+            return Ok(());
+        };
+        let (tx, rx) = oneshot::channel();
+        self.request_unsubscribe(sub_id, uri, Some(tx)).await?;
+        self.wait(rx).await
+    }
+    async fn request_unsubscribe(
+        &self,
+        sub_id: i64,
+        uri: String,
+        waiter: Option<UnsubscribeWaiter>,
+    ) -> Result<(), RtaError> {
+        let seq_id = self.next_sequence_id();
+        debug!(seq_id, sub_id, uri, "unsubscribe");
+        let unsubscribing = Unsubscribing {
+            sub_id,
+            uri,
+            waiter,
+        };
+        let request = Request::Unsubscribe { seq_id, sub_id }.encode()?;
+        self.registry().request_unsubscribe(seq_id, unsubscribing);
+        self.send_message(Message::Text(request)).await
+    }
+
+    /// A new consumer of the connection's events.
+    pub fn events(&self, filter: EventFilter) -> EventStream {
+        self.shared.bus.subscribe(filter)
+    }
+
+    /// Like [`WSWriter::events`], with its own overflow policy instead of the
+    /// client's.
+    pub fn events_with_policy(&self, filter: EventFilter, policy: OverflowPolicy) -> EventStream {
+        self.shared.bus.subscribe_with_policy(filter, policy)
+    }
+
+    /// Events dropped across all event streams so far.
+    pub fn dropped_events(&self) -> u64 {
+        self.shared.bus.dropped()
+    }
+
+    pub fn subscriptions(&self) -> Vec<ActiveSubscription> {
+        self.registry().list()
+    }
 
+    /// Closes the connection for good; the client will not reconnect afterwards.
     #[inline]
-    pub async fn close(&mut self) -> Result<()> {
+    pub async fn close(&self) -> Result<()> {
+        self.shared.closed.send_replace(true);
         self.send(Message::Close(None)).await?;
         Ok(())
     }
     #[inline]
-    pub async fn send(&mut self, message: Message) -> Result<()> {
-        self.sequence_id += 1;
-        self.writer.lock().await.send(message).await?;
+    pub async fn send(&self, message: Message) -> Result<()> {
+        self.send_message(message).await?;
+        Ok(())
+    }
+    async fn send_message(&self, message: Message) -> Result<(), RtaError> {
+        self.shared.writer.lock().await.send(message).await?;
         Ok(())
     }
+
+    #[inline]
+    pub fn is_closed(&self) -> bool {
+        *self.shared.closed.borrow()
+    }
+
+    /// Resolves once [`WSWriter::close`] was called.
+    async fn wait_closed(&self) {
+        let mut closed = self.shared.closed.subscribe();
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
+    async fn wait<T>(&self, rx: oneshot::Receiver<Result<T, RtaError>>) -> Result<T, RtaError> {
+        match tokio::time::timeout(self.shared.request_timeout, rx).await {
+            Ok(Ok(response)) => response,
+            Ok(Err(_)) => Err(RtaError::Closed),
+            Err(_) => Err(RtaError::Timeout),
+        }
+    }
+
+    /// `ConnectionId` of the current connection, known once the connections
+    /// resource (`RtaResource::Connections`) is subscribed.
+    pub fn connection_id(&self) -> Option<String> {
+        self.shared.connection_id.borrow().clone()
+    }
+
+    /// Follows [`WSWriter::connection_id`]; it goes back to `None` while a new
+    /// connection replays its subscriptions.
+    pub fn watch_connection_id(&self) -> watch::Receiver<Option<String>> {
+        self.shared.connection_id.subscribe()
+    }
+
+    /// Round-trip time of the last answered keepalive ping.
+    pub fn latency(&self) -> Option<Duration> {
+        *self.shared.latency.lock().unwrap()
+    }
+
+    /// Subscribes `uri` under the key of a subscription moved here from the
+    /// connection `from`, so that its stream carries on with this connection's
+    /// events.
+    pub(crate) async fn adopt(&self, key: u64, uri: &str, from: &WSWriter) -> Result<(), RtaError> {
+        from.shared.moved.lock().unwrap().insert(key, self.clone());
+        self.registry().insert(key, uri.to_owned(), None);
+        self.request_subscribe(key, uri, None).await
+    }
+
+    /// Registers a request of a recording under its recorded sequence id, so
+    /// that the recorded answer resolves it.
+    pub(crate) fn replay_request(&self, request: Request) {
+        let mut registry = self.registry();
+        match request {
+            Request::Subscribe { seq_id, uri } => {
+                let key = match registry.find(&uri) {
+                    // Only a new connection subscribes a live subscription again.
+                    Some((_, true)) => {
+                        debug!(seq_id, uri, "recorded connection replaced");
+                        registry.reset();
+                        registry.find(&uri).map(|(key, _)| key)
+                    }
+                    found => found.map(|(key, _)| key),
+                };
+                let key = key.unwrap_or_else(|| {
+                    let key = self.shared.bus.next_key();
+                    registry.insert(key, uri.clone(), None);
+                    key
+                });
+                debug!(seq_id, key, uri, "replayed subscribe");
+                registry.request(seq_id, key, None);
+            }
+            Request::Unsubscribe { seq_id, sub_id } => {
+                let uri = registry
+                    .key_of(sub_id)
+                    .and_then(|key| registry.remove(key))
+                    .map(|entry| entry.uri)
+                    .unwrap_or_default();
+                debug!(seq_id, sub_id, uri, "replayed unsubscribe");
+                let unsubscribing = Unsubscribing {
+                    sub_id,
+                    uri,
+                    waiter: None,
+                };
+                registry.request_unsubscribe(seq_id, unsubscribing);
+            }
+        }
+    }
+
+    /// The connection the subscription registered under `key` lives on now.
+    pub(crate) fn owner(&self, key: u64) -> WSWriter {
+        let mut owner = self.clone();
+        loop {
+            let moved = owner.shared.moved.lock().unwrap().get(&key).cloned();
+            match moved {
+                Some(moved) => owner = moved,
+                None => return owner,
+            }
+        }
+    }
+
+    pub(crate) fn same_connection(&self, other: &WSWriter) -> bool {
+        Arc::ptr_eq(&self.shared, &other.shared)
+    }
+
+    #[inline]
+    fn next_sequence_id(&self) -> i64 {
+        self.shared.sequence_id.fetch_add(1, Ordering::SeqCst)
+    }
+
+    #[inline]
+    pub(crate) fn registry(&self) -> MutexGuard<'_, Registry> {
+        self.shared.registry.lock().unwrap()
+    }
 }
 
 #[derive(Debug)]
 pub struct RtaStream {
     pre_subscription_urls: Vec<String>,
     ws_writer: WSWriter,
-    ws_reader: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-    rta_writer: Sender<RtaEvent>,
+    ws_reader: FrameStream,
+    reconnect: Option<(Connector, Backoff)>,
+    ping_interval: Duration,
+    max_missed_pongs: u32,
+    refresh_at: Option<Instant>,
+    subscribe_backoff: Backoff,
+    overflow: Option<mpsc::UnboundedSender<Overflowed>>,
+}
+
+/// Why [`RtaStream::read_until_disconnected`] stopped reading.
+enum Disconnect {
+    Lost(String),
+    /// The token is about to expire.
+    Refresh,
 }
+
 impl RtaStream {
-    pub fn new(
-        pre_sub_urls: Vec<String>,
-        ws_writer: WSWriter,
-        ws_reader: SplitStream<WebSocketStream<MaybeTlsStream<TcpStream>>>,
-        rta_writer: Sender<RtaEvent>,
-    ) -> Self {
+    pub fn new(pre_sub_urls: Vec<String>, ws_writer: WSWriter, ws_reader: FrameStream) -> Self {
         Self {
             pre_subscription_urls: pre_sub_urls,
             ws_writer,
             ws_reader,
-            rta_writer,
+            reconnect: None,
+            ping_interval: Duration::from_secs(20),
+            max_missed_pongs: 3,
+            refresh_at: None,
+            subscribe_backoff: Backoff::subscribe(),
+            overflow: None,
         }
     }
 
+    /// Paces retries of subscriptions refused as throttled or unavailable.
+    pub fn set_subscribe_backoff(mut self, backoff: Backoff) -> Self {
+        self.subscribe_backoff = backoff;
+        self
+    }
+
+    /// Pings every `interval` and gives the connection up once
+    /// `max_missed_pongs` pings in a row went unanswered.
+    pub fn set_keepalive(mut self, interval: Duration, max_missed_pongs: u32) -> Self {
+        self.ping_interval = interval;
+        self.max_missed_pongs = max_missed_pongs;
+        self
+    }
+
+    pub(crate) fn set_overflow(
+        mut self,
+        overflow: Option<mpsc::UnboundedSender<Overflowed>>,
+    ) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Hands every subscription to `overflow` once the client gave up, so that
+    /// they carry on on another connection.
+    fn hand_over(&self) {
+        let Some(overflow) = &self.overflow else {
+            return;
+        };
+        let keys: Vec<_> = self
+            .ws_writer
+            .subscriptions()
+            .iter()
+            .map(|s| s.key)
+            .collect();
+        for key in keys {
+            let Some(Entry { uri, .. }) = self.ws_writer.registry().remove(key) else {
+                continue;
+            };
+            info!(uri, "moving subscription off the stopped connection");
+            // Never sent; identifies the move if it fails.
+            let seq_id = self.ws_writer.next_sequence_id();
+            let _ = overflow.send(Overflowed {
+                seq_id,
+                key,
+                uri,
+                from: self.ws_writer.clone(),
+            });
+        }
+    }
+
+    pub(crate) fn set_refresh_at(mut self, refresh_at: Option<Instant>) -> Self {
+        self.refresh_at = refresh_at;
+        self
+    }
+
+    pub(crate) fn set_reconnect(mut self, connector: Connector, backoff: Backoff) -> Self {
+        self.reconnect = Some((connector, backoff));
+        self
+    }
+
     pub async fn run(&mut self) -> Result<()> {
-        for sub in self.pre_subscription_urls.iter() {
-            self.ws_writer.subscribe(sub).await?;
+        for uri in std::mem::take(&mut self.pre_subscription_urls) {
+            let key = self.ws_writer.shared.bus.next_key();
+            self.ws_writer.registry().insert(key, uri.clone(), None);
+            self.ws_writer.request_subscribe(key, &uri, None).await?;
         }
         loop {
-            match tokio::time::timeout(Duration::from_millis(30000), self.ws_reader.next()).await {
-                Ok(Some(Ok(msg))) => match msg {
-                    Message::Text(v) => match serde_json::from_str(&v)? {
-                        MessageData::Subscribe {
-                            seq_id,
-                            status,
-                            sub_id,
-                            connection_id,
-                        } => {
-                            if status == Status::Success {
-                                self.rta_writer
-                                    .send(RtaEvent::Subscribe {
-                                        seq_id,
-                                        sub_id,
-                                        connection_id,
-                                    })
-                                    .await?;
-                            } else {
-                                bail!("Subscribe failed: {status:?}");
-                            }
-                        }
-                        MessageData::Unsubscribe { seq_id, status } => {
-                            if status == Status::Success {
-                                self.rta_writer
-                                    .send(RtaEvent::Unsubscribe { seq_id })
-                                    .await?;
-                            } else {
-                                bail!("Unsubscribe failed: {status:?}");
-                            }
-                        }
-                        MessageData::Event { sub_id, data } => {
-                            self.rta_writer
-                                .send(RtaEvent::Event { sub_id, data })
-                                .await?;
-                        }
-                        MessageData::Resync => {}
-                    },
+            let reason = match self.read_until_disconnected().await? {
+                Disconnect::Refresh => {
+                    self.refresh().await?;
+                    continue;
+                }
+                Disconnect::Lost(reason) => reason,
+            };
+            if self.ws_writer.is_closed() || self.reconnect.is_none() {
+                break;
+            }
+            warn!(reason, "disconnected");
+            self.publish(RtaEvent::Disconnected { reason }).await;
+            self.reconnect().await?;
+            if self.ws_writer.is_closed() {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    async fn read_until_disconnected(&mut self) -> Result<Disconnect> {
+        let mut keepalive = Keepalive::new(self.max_missed_pongs);
+        let mut ticker =
+            tokio::time::interval_at(Instant::now() + self.ping_interval, self.ping_interval);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let refresh_at = self.refresh_at;
+        let refresh = async move {
+            match refresh_at {
+                Some(refresh_at) => tokio::time::sleep_until(refresh_at).await,
+                None => std::future::pending().await,
+            }
+        };
+        tokio::pin!(refresh);
+        loop {
+            let frame = tokio::select! {
+                frame = self.ws_reader.next() => frame,
+                _ = &mut refresh => break Ok(Disconnect::Refresh),
+                _ = ticker.tick() => {
+                    let Some(payload) = keepalive.ping() else {
+                        let missed = keepalive.missed();
+                        warn!(missed, "pongs missed");
+                        let _ = self.ws_writer.send(Message::Close(None)).await;
+                        break Ok(Disconnect::Lost(format!("{missed} pongs missed")));
+                    };
+                    if let Err(e) = self.ws_writer.send_message(Message::Ping(payload)).await {
+                        warn!(error = %e, "ping failed");
+                        break Ok(Disconnect::Lost(format!("WebSocket error: {e}")));
+                    }
+                    continue;
+                }
+            };
+            match frame {
+                Some(Ok(msg)) => match msg {
+                    Message::Text(v) => self.on_text(v).await?,
                     Message::Close(v) => {
-                        println!("Close:{v:?}");
+                        debug!(frame = ?v, "close frame");
                     }
-                    Message::Pong(v) => {
-                        self.rta_writer.send(RtaEvent::Pong(v)).await?;
+                    Message::Pong(payload) => {
+                        let latency = keepalive.pong(&payload);
+                        if latency.is_some() {
+                            *self.ws_writer.shared.latency.lock().unwrap() = latency;
+                        }
+                        self.publish(RtaEvent::Pong { payload, latency }).await;
                     }
                     _ => {}
                 },
-                Ok(Some(Err(e))) => {
-                    println!("WebSocketErrorOccured: {e:?}.");
-                    break;
+                Some(Err(e)) => {
+                    warn!(error = %e, "websocket error");
+                    break Ok(Disconnect::Lost(format!("WebSocket error: {e}")));
                 }
-                Ok(None) => {
-                    println!("WebSocketDisconnected.");
-                    break;
+                None => {
+                    debug!("websocket ended");
+                    break Ok(Disconnect::Lost("WebSocket disconnected".to_owned()));
                 }
-                Err(..) => {
-                    println!("Timeout: no response in 30000 milliseconds.");
-                    self.ws_writer.send(Message::Close(None)).await?;
-                    break;
+            }
+        }
+    }
+
+    async fn on_text(&mut self, frame: String) -> Result<()> {
+        match serde_json::from_str(&frame) {
+            Ok(message) => self.on_message(message).await,
+            // One bad frame shouldn't cost the whole connection.
+            Err(e) => {
+                warn!(error = %e, "malformed frame");
+                let event = RtaEvent::Malformed {
+                    frame,
+                    error: e.to_string(),
+                };
+                self.publish(event).await;
+                Ok(())
+            }
+        }
+    }
+
+    async fn on_message(&mut self, message: MessageData) -> Result<()> {
+        match message {
+            MessageData::Subscribe {
+                seq_id,
+                status,
+                sub_id,
+                payload,
+            } => self.on_subscribe(seq_id, status, sub_id, payload).await?,
+            MessageData::Unsubscribe { seq_id, status } => {
+                self.on_unsubscribe(seq_id, status).await?
+            }
+            MessageData::Event { sub_id, data } => {
+                let source = self.ws_writer.registry().source_of(sub_id);
+                trace!(sub_id, known = source.is_some(), "event");
+                self.ws_writer
+                    .shared
+                    .bus
+                    .publish(source, RtaEvent::Event { sub_id, data })
+                    .await;
+            }
+            MessageData::Resync => {
+                info!("resync");
+                self.publish(RtaEvent::Resync).await;
+                self.resync();
+            }
+            MessageData::Unknown { msg_type, fields } => {
+                warn!(msg_type, "unknown message type");
+                let event = RtaEvent::Malformed {
+                    frame: serde_json::to_string(&MessageData::Unknown { msg_type, fields })?,
+                    error: format!("Unknown message type {msg_type}"),
+                };
+                self.publish(event).await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the resync hooks in the background so the connection keeps being
+    /// read meanwhile.
+    fn resync(&self) {
+        let resyncs: Vec<_> = self
+            .ws_writer
+            .registry()
+            .resyncs()
+            .into_iter()
+            .map(|(source, sub_id, entry, hook)| (source, sub_id, entry.uri.clone(), hook.clone()))
+            .collect();
+        let connector = self
+            .reconnect
+            .as_ref()
+            .map(|(connector, _)| connector.clone());
+        for (source, sub_id, uri, hook) in resyncs {
+            let bus = self.ws_writer.shared.bus.clone();
+            let connector = connector.clone();
+            tokio::spawn(async move {
+                let event = match hook.run(uri.clone(), connector.as_ref()).await {
+                    Ok(state) => RtaEvent::Resynced { sub_id, uri, state },
+                    Err(e) => RtaEvent::ResyncFailed {
+                        sub_id,
+                        uri,
+                        error: format!("{e:?}"),
+                    },
+                };
+                bus.publish(Some(source), event).await;
+            });
+        }
+    }
+
+    /// Publishes an event that belongs to no subscription.
+    #[inline]
+    async fn publish(&self, event: RtaEvent) {
+        self.ws_writer.shared.bus.publish(None, event).await;
+    }
+
+    async fn on_subscribe(
+        &mut self,
+        seq_id: i64,
+        status: Status,
+        sub_id: Option<i64>,
+        payload: serde_json::Value,
+    ) -> Result<()> {
+        let resolved = self.ws_writer.registry().resolve(seq_id);
+        match (sub_id.filter(|_| status == Status::Success), resolved) {
+            (Some(sub_id), Some((key, waiter))) => {
+                debug!(seq_id, sub_id, key, "subscribed");
+                self.ws_writer.registry().activate(key, sub_id);
+                let event = RtaEvent::Subscribe {
+                    seq_id,
+                    sub_id,
+                    payload: payload.clone(),
+                };
+                let source = self.ws_writer.registry().source(key);
+                if source.is_some_and(|source| source.kind == Some(ResourceKind::Connections)) {
+                    self.on_connection_id(&payload).await;
+                }
+                if let Some(waiter) = waiter {
+                    waiter.send(Ok(payload));
+                }
+                self.ws_writer.shared.bus.publish(source, event).await;
+            }
+            // The caller gave up waiting, so don't leak the subscription. It
+            // ends with the connection anyway should that be closing.
+            (Some(sub_id), None) => {
+                debug!(seq_id, sub_id, "unsubscribing abandoned subscription");
+                if let Err(e) = self.ws_writer.unsubscribe(sub_id).await {
+                    warn!(error = format!("{e:#}"), sub_id, "unsubscribe failed");
+                }
+            }
+            (None, Some((key, waiter))) => {
+                self.on_subscribe_failed(seq_id, key, waiter, status).await
+            }
+            (None, None) => {}
+        }
+        Ok(())
+    }
+
+    async fn on_connection_id(&mut self, payload: &serde_json::Value) {
+        let connection_id = match ConnectionInfo::deserialize(payload) {
+            Ok(info) => info.connection_id,
+            Err(e) => {
+                warn!(error = %e, "invalid connection info");
+                return;
+            }
+        };
+        let changed = self
+            .ws_writer
+            .shared
+            .connection_id
+            .send_if_modified(|current| {
+                let changed = current.as_ref() != Some(&connection_id);
+                *current = Some(connection_id.clone());
+                changed
+            });
+        if changed {
+            info!(connection_id, "connection id changed");
+            self.publish(RtaEvent::ConnectionIdChanged { connection_id })
+                .await;
+        }
+    }
+
+    async fn on_subscribe_failed(
+        &mut self,
+        seq_id: i64,
+        key: u64,
+        waiter: Option<SubscribeWaiter>,
+        status: Status,
+    ) {
+        if status.is_transient() {
+            let retry = self
+                .ws_writer
+                .registry()
+                .schedule_retry(key, &self.subscribe_backoff);
+            // A caller that would stop waiting before the retry is sent gets
+            // the refusal instead of a timeout.
+            let retry = retry
+                .filter(|(.., delay)| waiter.as_ref().is_none_or(|waiter| waiter.outlasts(*delay)));
+            if let Some((uri, attempt, delay)) = retry {
+                info!(seq_id, ?status, uri, attempt, ?delay, "retrying subscribe");
+                let source = self.ws_writer.registry().source(key);
+                let event = RtaEvent::SubscribeRetrying {
+                    uri: uri.clone(),
+                    status,
+                    attempt,
+                    delay,
+                };
+                self.ws_writer.shared.bus.publish(source, event).await;
+                let writer = self.ws_writer.clone();
+                let retry = async move {
+                    tokio::time::sleep(delay).await;
+                    let Some(waiter) = writer.registry().take_retry(key, waiter) else {
+                        return;
+                    };
+                    if let Err(e) = writer.request_subscribe(key, &uri, waiter).await {
+                        warn!(error = %e, uri, "subscribe retry failed");
+                    }
+                };
+                tokio::spawn(retry.in_current_span());
+                return;
+            }
+        }
+        if let (Status::SubscriptionLimitReached, None, Some(overflow)) =
+            (status, &waiter, &self.overflow)
+        {
+            let entry = self.ws_writer.registry().remove(key);
+            if let Some(Entry { uri, .. }) = entry {
+                info!(seq_id, uri, "moving subscription to another connection");
+                let from = self.ws_writer.clone();
+                let _ = overflow.send(Overflowed {
+                    seq_id,
+                    key,
+                    uri,
+                    from,
+                });
+                return;
+            }
+        }
+        let uri = self
+            .ws_writer
+            .registry()
+            .remove(key)
+            .map(|entry| entry.uri)
+            .unwrap_or_default();
+        warn!(seq_id, ?status, uri, "subscribe failed");
+        match waiter {
+            Some(waiter) => {
+                waiter.send(Err(RtaError::Status(status)));
+            }
+            None => {
+                self.publish(RtaEvent::SubscribeFailed {
+                    seq_id,
+                    uri,
+                    status,
+                })
+                .await
+            }
+        }
+    }
+
+    async fn on_unsubscribe(&mut self, seq_id: i64, status: Status) -> Result<()> {
+        let Some(Unsubscribing {
+            sub_id,
+            uri,
+            waiter,
+        }) = self.ws_writer.registry().resolve_unsubscribe(seq_id)
+        else {
+            return Ok(());
+        };
+        let result = match status {
+            Status::Success => Ok(()),
+            status => Err(RtaError::Status(status)),
+        };
+        match (waiter, result) {
+            (Some(waiter), result) => {
+                let _ = waiter.send(result);
+            }
+            (None, Err(e)) => warn!(error = %e, uri, "unsubscribe failed"),
+            (None, Ok(())) => {}
+        }
+        if status == Status::Success {
+            self.publish(RtaEvent::Unsubscribe {
+                seq_id,
+                sub_id,
+                uri,
+            })
+            .await;
+        }
+        Ok(())
+    }
+
+    async fn reconnect(&mut self) -> Result<()> {
+        let Some((connector, backoff)) = self.reconnect.clone() else {
+            bail!("Reconnecting is not configured.");
+        };
+        let mut attempts = 0;
+        loop {
+            let Some(delay) = backoff.delay(attempts) else {
+                bail!("Gave up reconnecting after {attempts} attempts.");
+            };
+            attempts += 1;
+            let connect = async {
+                tokio::time::sleep(delay).await;
+                connector.connect().await
+            };
+            let connected = tokio::select! {
+                connected = connect => connected,
+                () = self.ws_writer.wait_closed() => {
+                    info!(attempts, "closed while reconnecting");
+                    return Ok(());
+                }
+            };
+            let connection = match connected {
+                Ok(connection) => connection,
+                Err(e) => {
+                    warn!(error = format!("{e:#}"), attempts, "reconnect failed");
+                    continue;
+                }
+            };
+            info!(attempts, "reconnected");
+            match self
+                .replace(connection, RtaEvent::Reconnected { attempts })
+                .await
+            {
+                Ok(()) => return Ok(()),
+                Err(e) => self.replay_failed(e).await,
+            }
+        }
+    }
+
+    /// Reports a connection lost while its subscriptions were replayed, which
+    /// counts as another disconnect.
+    async fn replay_failed(&self, e: RtaError) {
+        let reason = format!("Replaying subscriptions failed: {e}");
+        warn!(reason, "disconnected");
+        self.publish(RtaEvent::Disconnected { reason }).await;
+    }
+
+    /// Moves to a connection with a fresh token before the current one expires.
+    /// The new socket is opened first, and the old one closed and read to its
+    /// end before anything is replayed, so no event is lost or arrives twice.
+    async fn refresh(&mut self) -> Result<()> {
+        let Some((connector, _)) = &self.reconnect else {
+            self.refresh_at = None;
+            return Ok(());
+        };
+        match connector.connect().await {
+            Ok(connection) => {
+                let _ = self.ws_writer.send_message(Message::Close(None)).await;
+                self.drain().await?;
+                info!("refreshed connection");
+                if let Err(e) = self.replace(connection, RtaEvent::Refreshed).await {
+                    self.replay_failed(e).await;
+                    return self.reconnect().await;
                 }
+                Ok(())
             }
+            // The current token is still good for a while, so try again later.
+            Err(e) => {
+                warn!(error = format!("{e:#}"), "refresh failed");
+                self.refresh_at = Some(Instant::now() + Duration::from_secs(30));
+                Ok(())
+            }
+        }
+    }
+
+    /// Handles the frames RTA sent on the closing connection before it answered
+    /// the close, waiting no longer than the request timeout.
+    async fn drain(&mut self) -> Result<()> {
+        let request_timeout = self.ws_writer.shared.request_timeout;
+        let drained = tokio::time::timeout(request_timeout, async {
+            while let Some(Ok(message)) = self.ws_reader.next().await {
+                if let Message::Text(frame) = message {
+                    self.on_text(frame).await?;
+                }
+            }
+            anyhow::Ok(())
+        });
+        match drained.await {
+            Ok(drained) => drained,
+            Err(_) => {
+                warn!("connection did not close in time");
+                Ok(())
+            }
+        }
+    }
+
+    /// Switches to `connection` and replays every subscription on it. Should
+    /// the new socket fail too, the subscriptions stay registered for the next
+    /// one.
+    async fn replace(&mut self, connection: Connection, event: RtaEvent) -> Result<(), RtaError> {
+        let Connection {
+            writer,
+            reader,
+            refresh_at,
+        } = connection;
+        *self.ws_writer.shared.writer.lock().await = writer;
+        self.ws_reader = reader;
+        self.refresh_at = Some(refresh_at);
+        // The new connection gets its own id once its subscriptions replay.
+        self.ws_writer.shared.connection_id.send_replace(None);
+        self.publish(event).await;
+
+        let subscriptions = self.ws_writer.registry().reset();
+        let mut requests = vec![];
+        for (key, uri, waiter) in subscriptions {
+            requests.push(self.ws_writer.register_subscribe(key, &uri, waiter)?);
+        }
+        for request in requests {
+            self.ws_writer.send_message(Message::Text(request)).await?;
         }
         Ok(())
     }
@@ -194,13 +1023,691 @@ impl RtaStream {
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
 
-    use anyhow::Result;
-    use tokio::sync::Mutex;
+    use anyhow::{Context, Result};
+    use futures_util::{future::BoxFuture, sink, stream};
+    use serde_json::json;
+    use tokio::{sync::Mutex, time::Instant};
+    use tokio_tungstenite::tungstenite::{self, Message};
     use xbl_auth::XBLAuth;
 
-    use crate::builder::RtaClientBuilder;
+    use crate::{
+        backoff::Backoff,
+        builder::RtaClientBuilder,
+        bus::EventStream,
+        error::RtaError,
+        event::RtaEvent,
+        handle::Termination,
+        message::Request,
+        mock::{self, MockRta, Reply},
+        record::{Direction, Recorder},
+        resource::{PresenceState, ResourceData, RtaResource, SocialChange, SocialNotification},
+        status::Status,
+        transport::{FrameSink, FrameStream, RtaTransport, TcpTransport},
+    };
+
+    fn fast() -> Backoff {
+        Backoff {
+            initial: Duration::from_millis(10),
+            max: Duration::from_millis(50),
+            multiplier: 2,
+            max_attempts: Some(5),
+        }
+    }
+
+    async fn builder(mock: &MockRta) -> Result<RtaClientBuilder> {
+        let builder = RtaClientBuilder::new(mock::xbl_auth().await?)
+            .set_uri(mock.uri())
+            .set_reconnect_backoff(fast())
+            .set_subscribe_backoff(fast())
+            .set_request_timeout(Duration::from_secs(1));
+        Ok(builder)
+    }
+
+    /// The next event other than a pong.
+    async fn next(events: &mut EventStream) -> RtaEvent {
+        let event = async {
+            loop {
+                match events.recv().await {
+                    Some(RtaEvent::Pong { .. }) => continue,
+                    Some(event) => return event,
+                    None => panic!("event stream closed"),
+                }
+            }
+        };
+        tokio::time::timeout(Duration::from_secs(5), event)
+            .await
+            .expect("no event")
+    }
+
+    #[tokio::test]
+    async fn subscribes_and_shuts_down() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::Connections.uri();
+        mock.set_reply(&uri, Reply::success(json!({ "ConnectionId": "c0" })));
+        let (handle, _) = builder(&mock).await?.connect().await?.listen()?;
+        let mut subscription = handle.subscribe(&uri).await?;
+        assert_eq!(handle.connection_id().as_deref(), Some("c0"));
+        assert_eq!(mock.authorizations(), ["XBL3.0 x=1234567890;mock"]);
+
+        assert!(matches!(
+            subscription.recv().await,
+            Some(RtaEvent::Subscribe { .. })
+        ));
+        let data = json!({ "Ncid": "n0", "ShoulderTaps": [] });
+        assert_eq!(mock.event(&uri, data), 1);
+        assert!(matches!(
+            subscription.recv().await,
+            Some(RtaEvent::Event { .. })
+        ));
+
+        let termination = handle.shutdown(Duration::from_secs(5)).await;
+        assert!(matches!(termination, Termination::Closed), "{termination}");
+        assert!(mock.subscriptions().is_empty());
+        assert!(matches!(
+            mock.requests()[..],
+            [Request::Subscribe { .. }, Request::Unsubscribe { .. }]
+        ));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn routes_presence_and_social_events() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let presence = RtaResource::presence("2535400000000000").uri();
+        let social = RtaResource::social("2535400000000000").uri();
+        let (handle, _) = builder(&mock).await?.connect().await?.listen()?;
+        let mut presence = handle.subscribe(&presence).await?;
+        let mut social = handle.subscribe(&social).await?;
+
+        let frames = [
+            (&mut presence, json!("Offline")),
+            (
+                &mut social,
+                json!({ "NotificationType": "Added", "Xuids": ["2535400000000001"] }),
+            ),
+        ];
+        let mut decoded = vec![];
+        for (subscription, data) in frames {
+            assert_eq!(mock.event(subscription.uri(), data), 1);
+            let event = loop {
+                match subscription.recv().await {
+                    Some(RtaEvent::Event { sub_id, data }) => break (sub_id, data),
+                    Some(_) => continue,
+                    None => panic!("subscription ended"),
+                }
+            };
+            assert_eq!(Some(event.0), subscription.sub_id());
+            decoded.push(subscription.decode_event(&event.1)?);
+        }
+        assert_eq!(
+            decoded,
+            [
+                ResourceData::Presence(PresenceState::Offline),
+                ResourceData::Social(SocialChange {
+                    notification_type: SocialNotification::Added,
+                    xuids: vec!["2535400000000001".into()],
+                }),
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn stops_clients_that_do_not_close_in_time() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::presence("2535400000000000").uri();
+        let (handle, _) = builder(&mock).await?.connect().await?.listen()?;
+        let _subscription = handle.subscribe(&uri).await?;
+        let writer = handle.writer().clone();
+
+        mock.set_silent(true);
+        let started = Instant::now();
+        let termination = handle.shutdown(Duration::from_millis(200)).await;
+        assert!(matches!(termination, Termination::Timeout), "{termination}");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(writer.is_closed());
+        // Neither the unsubscribe nor the close was answered.
+        assert_eq!(mock.subscriptions(), [uri]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn retries_transient_failures() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let presence = RtaResource::presence("2535400000000000").uri();
+        let social = RtaResource::social("2535400000000000").uri();
+        mock.push_reply(&presence, Reply::status(Status::Throttled));
+        mock.set_reply(&social, Reply::status(Status::UnknownResource));
+        let (handle, mut events) = builder(&mock).await?.connect().await?.listen()?;
+
+        let _subscription = handle.subscribe(&presence).await?;
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::SubscribeRetrying {
+                status: Status::Throttled,
+                ..
+            }
+        ));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Subscribe { .. }
+        ));
+
+        assert!(matches!(
+            handle.subscribe(&social).await,
+            Err(RtaError::Status(Status::UnknownResource))
+        ));
+        assert_eq!(mock.subscriptions(), [presence]);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_retrying_before_timing_out() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::presence("2535400000000000").uri();
+        mock.set_reply(&uri, Reply::status(Status::Throttled));
+        let builder = RtaClientBuilder::new(mock::xbl_auth().await?).set_uri(mock.uri());
+        let (handle, _events) = builder.connect().await?.listen()?;
+
+        // Retries after 1, 2 and 4 seconds fit in the 10 second timeout, the
+        // next one after 8 more does not.
+        let started = Instant::now();
+        assert!(matches!(
+            handle.subscribe(&uri).await,
+            Err(RtaError::Status(Status::Throttled))
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(mock.requests().len(), 4);
+        Ok(())
+    }
+
+    /// Connects over TCP, except that the socket of connection number
+    /// `broken` cannot be written to.
+    #[derive(Debug)]
+    struct BrokenWriter {
+        broken: usize,
+        connects: AtomicUsize,
+    }
+
+    impl RtaTransport for BrokenWriter {
+        fn connect(
+            &self,
+            request: tungstenite::handshake::client::Request,
+        ) -> BoxFuture<'_, Result<(FrameSink, FrameStream)>> {
+            Box::pin(async move {
+                let (writer, reader) = TcpTransport.connect(request).await?;
+                if self.connects.fetch_add(1, Ordering::SeqCst) != self.broken {
+                    return Ok((writer, reader));
+                }
+                let broken = sink::unfold((), |(), _: Message| async {
+                    Err::<(), _>(tungstenite::Error::ConnectionClosed)
+                });
+                Ok((FrameSink::new(broken), reader))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn reconnects_again_when_replaying_fails() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::presence("2535400000000000").uri();
+        let transport = BrokenWriter {
+            broken: 1,
+            connects: AtomicUsize::new(0),
+        };
+        let builder = builder(&mock).await?.set_transport(transport);
+        let (handle, mut events) = builder.connect().await?.listen()?;
+        let _subscription = handle.subscribe(&uri).await?;
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Subscribe { .. }
+        ));
+
+        mock.disconnect();
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Disconnected { .. }
+        ));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Reconnected { attempts: 1 }
+        ));
+        let RtaEvent::Disconnected { reason } = next(&mut events).await else {
+            panic!("replayed on a broken socket");
+        };
+        assert!(reason.starts_with("Replaying subscriptions failed"));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Reconnected { attempts: 2 }
+        ));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Subscribe { .. }
+        ));
+        assert_eq!(mock.subscriptions(), [uri]);
+        assert!(!handle.is_finished());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn shuts_down_while_reconnecting() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::presence("2535400000000000").uri();
+        let slow = Backoff {
+            initial: Duration::from_secs(1),
+            ..fast()
+        };
+        let builder = builder(&mock).await?.set_reconnect_backoff(slow);
+        let (handle, mut events) = builder.connect().await?.listen()?;
+        let _subscription = handle.subscribe(&uri).await?;
+        mock.disconnect();
+        while !matches!(next(&mut events).await, RtaEvent::Disconnected { .. }) {}
+
+        let started = Instant::now();
+        let termination = handle.shutdown(Duration::from_secs(5)).await;
+        assert!(matches!(termination, Termination::Closed), "{termination}");
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert_eq!(mock.accepted(), 1);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn reconnects_and_resubscribes() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::presence("2535400000000000").uri();
+        let (handle, mut events) = builder(&mock).await?.connect().await?.listen()?;
+        let subscription = handle.subscribe(&uri).await?;
+        let RtaEvent::Subscribe { sub_id: first, .. } = next(&mut events).await else {
+            panic!("not subscribed");
+        };
+
+        mock.disconnect();
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Disconnected { .. }
+        ));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Reconnected { .. }
+        ));
+        let RtaEvent::Subscribe { sub_id, .. } = next(&mut events).await else {
+            panic!("subscription not replayed");
+        };
+        assert_ne!(sub_id, first);
+        assert_eq!(subscription.sub_id(), Some(sub_id));
+        assert_eq!(mock.accepted(), 2);
+        assert_eq!(mock.subscriptions(), [uri]);
+
+        mock.resync();
+        assert!(matches!(next(&mut events).await, RtaEvent::Resync));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn announces_new_connection_ids() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::Connections.uri();
+        mock.push_reply(&uri, Reply::success(json!({ "ConnectionId": "c0" })));
+        mock.set_reply(&uri, Reply::success(json!({ "ConnectionId": "c1" })));
+        let (handle, mut events) = builder(&mock).await?.connect().await?.listen()?;
+        let mut connection_id = handle.watch_connection_id();
+        assert!(connection_id.borrow().is_none());
+
+        let _subscription = handle.subscribe(&uri).await?;
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::ConnectionIdChanged { connection_id } if connection_id == "c0"
+        ));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Subscribe { .. }
+        ));
+        assert_eq!(connection_id.borrow_and_update().as_deref(), Some("c0"));
+
+        mock.disconnect();
+        loop {
+            match next(&mut events).await {
+                RtaEvent::ConnectionIdChanged { connection_id } => {
+                    assert_eq!(connection_id, "c1");
+                    break;
+                }
+                RtaEvent::Subscribe { .. } => panic!("resubscribed before the new id"),
+                _ => {}
+            }
+        }
+        assert_eq!(handle.connection_id().as_deref(), Some("c1"));
+        assert!(connection_id.has_changed()?);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_reconnecting() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let (handle, mut events) = builder(&mock).await?.connect().await?.listen()?;
+        // Closes the listener too, so every reconnect attempt is refused.
+        drop(mock);
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Disconnected { .. }
+        ));
+        let termination = tokio::time::timeout(Duration::from_secs(5), handle.join()).await?;
+        let Termination::Error(e) = termination else {
+            panic!("ended with {termination}");
+        };
+        assert_eq!(e.to_string(), "Gave up reconnecting after 5 attempts.");
+        // Consumers are not left waiting.
+        assert!(events.recv().await.is_none());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_zero_ping_interval() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let connected = builder(&mock)
+            .await?
+            .set_ping_interval(Duration::ZERO)
+            .connect()
+            .await;
+        let Err(e) = connected else {
+            panic!("connected without pings");
+        };
+        assert_eq!(e.to_string(), "The ping interval is zero.");
+        assert_eq!(mock.accepted(), 0);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_long_token_refresh_margins() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let margin = crate::builder::MAX_TOKEN_REFRESH_MARGIN + Duration::from_secs(1);
+        let connected = builder(&mock)
+            .await?
+            .set_token_refresh_margin(margin)
+            .connect()
+            .await;
+        assert!(connected.is_err());
+        assert_eq!(mock.accepted(), 0);
+        Ok(())
+    }
+
+    /// A connection that sends `last` once the client closes it, then ends.
+    /// Nothing can be sent on it after the close.
+    fn closing_connection(last: &'static str) -> (FrameStream, FrameSink) {
+        let (inbound, rx) = tokio::sync::mpsc::unbounded_channel();
+        let reader = stream::unfold(rx, |mut rx| async move {
+            let message = rx.recv().await?;
+            Some((Ok(message), rx))
+        });
+        let writer = sink::unfold(Some(inbound), move |inbound, message: Message| async move {
+            let Some(inbound) = inbound else {
+                return Err(tungstenite::Error::AlreadyClosed);
+            };
+            if let Message::Close(_) = message {
+                let _ = inbound.send(Message::Text(last.into()));
+                return Ok(None);
+            }
+            Ok(Some(inbound))
+        });
+        (FrameStream::new(reader), FrameSink::new(writer))
+    }
+
+    #[tokio::test]
+    async fn refreshes_despite_late_answers() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::presence("2535400000000000").uri();
+        let mut client = builder(&mock)
+            .await?
+            .add_subscription(uri.clone())
+            .connect()
+            .await?;
+        // Answers a subscribe whose caller gave up, once the connection can no
+        // longer carry the unsubscribe.
+        let (reader, writer) = closing_connection(r#"[1,9,0,12,"Online"]"#);
+        client.ws_reader = reader;
+        *client.ws_writer.shared.writer.lock().await = writer;
+        client.refresh_at = Some(Instant::now());
+        let (handle, mut events) = client.listen()?;
+
+        assert!(matches!(next(&mut events).await, RtaEvent::Refreshed));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Subscribe { .. }
+        ));
+        assert_eq!(mock.subscriptions(), [uri]);
+        assert!(!handle.is_finished());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn refreshes_connections() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let uri = RtaResource::presence("2535400000000000").uri();
+        let mut client = builder(&mock)
+            .await?
+            .add_subscription(uri.clone())
+            .connect()
+            .await?;
+        // The mock token lasts an hour, well beyond the refresh margin.
+        let refresh_at = client.refresh_at.context("No refresh planned.")?;
+        assert!(refresh_at > Instant::now() + Duration::from_secs(3000));
+        // Stands in for the current connection, which RTA only sends an event
+        // on once the client closes it.
+        let (reader, writer) = closing_connection(r#"[3,7,{"Ncid":"n0","ShoulderTaps":[]}]"#);
+        client.ws_reader = reader;
+        *client.ws_writer.shared.writer.lock().await = writer;
+        client.refresh_at = Some(Instant::now());
+        let (handle, mut events) = client.listen()?;
+
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Event { sub_id: 7, .. }
+        ));
+        assert!(matches!(next(&mut events).await, RtaEvent::Refreshed));
+        assert!(matches!(
+            next(&mut events).await,
+            RtaEvent::Subscribe { seq_id: 1, .. }
+        ));
+        assert_eq!(mock.subscriptions(), [uri]);
+        assert_eq!(mock.accepted(), 2);
+        assert!(!handle.is_finished());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn gives_up_on_silent_servers() -> Result<()> {
+        let mock = MockRta::start().await?;
+        let (handle, mut events) = builder(&mock)
+            .await?
+            .set_request_timeout(Duration::from_millis(200))
+            .set_ping_interval(Duration::from_millis(50))
+            .set_max_missed_pongs(2)
+            .connect()
+            .await?
+            .listen()?;
+        mock.set_silent(true);
+        let uri = RtaResource::presence("2535400000000000").uri();
+        assert!(matches!(
+            handle.subscribe(&uri).await,
+            Err(RtaError::Timeout)
+        ));
+        let RtaEvent::Disconnected { reason } = next(&mut events).await else {
+            panic!("silence went unnoticed");
+        };
+        assert!(reason.contains("pongs missed"), "{reason}");
+
+        mock.broadcast(Message::Text("not json".into()));
+        mock.set_silent(false);
+        loop {
+            if let RtaEvent::Malformed { frame, .. } = next(&mut events).await {
+                assert_eq!(frame, "not json");
+                break;
+            }
+        }
+        // So is an event without a body.
+        mock.broadcast(Message::Text("[3,1]".into()));
+        loop {
+            if let RtaEvent::Malformed { frame, .. } = next(&mut events).await {
+                assert_eq!(frame, "[3,1]");
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replays_recordings() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "rta-replay-{}.jsonl",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let recorder = Recorder::create(&path)?;
+        let subscribe = Request::Subscribe {
+            seq_id: 0,
+            uri: "https://sessiondirectory.xboxlive.com/connections/".into(),
+        };
+        let frames = [
+            (Direction::Outbound, subscribe.encode()?),
+            (
+                Direction::Inbound,
+                r#"[1,0,0,1,{"ConnectionId":"c0"}]"#.into(),
+            ),
+            (
+                Direction::Inbound,
+                r#"[3,1,{"Ncid":"n0","ShoulderTaps":[]}]"#.into(),
+            ),
+        ];
+        for (direction, frame) in frames {
+            recorder.record(direction, &Message::Text(frame));
+        }
+        recorder.flush().await;
+
+        let xbl_auth = Arc::new(Mutex::new(XBLAuth::new(
+            "../../auth".parse()?,
+            "Ferris".into(),
+        )));
+        let client = RtaClientBuilder::new(xbl_auth).replay(&path)?;
+        std::fs::remove_file(&path)?;
+        let (handle, mut events) = client.listen()?;
+        let connection_id = handle.watch_connection_id();
+        let mut received = vec![];
+        while let Some(event) = events.recv().await {
+            received.push(event);
+        }
+        assert!(matches!(
+            &received[..],
+            [
+                RtaEvent::ConnectionIdChanged { connection_id },
+                RtaEvent::Subscribe { sub_id: 1, .. },
+                RtaEvent::Event { sub_id: 1, .. },
+            ] if connection_id == "c0"
+        ));
+        assert_eq!(connection_id.borrow().as_deref(), Some("c0"));
+        assert!(matches!(handle.join().await, Termination::Closed));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn replays_recordings_across_reconnects() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "rta-replay-{}.jsonl",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let recorder = Recorder::create(&path)?;
+        let presence = RtaResource::presence("2535400000000000").uri();
+        let social = RtaResource::social("2535400000000000").uri();
+        let requests = [
+            Request::Subscribe {
+                seq_id: 0,
+                uri: presence.clone(),
+            },
+            Request::Subscribe {
+                seq_id: 1,
+                uri: social,
+            },
+            Request::Unsubscribe {
+                seq_id: 2,
+                sub_id: 2,
+            },
+            // Replayed by a reconnect.
+            Request::Subscribe {
+                seq_id: 3,
+                uri: presence,
+            },
+        ];
+        let requests: Vec<_> = requests
+            .iter()
+            .map(|request| Ok((Direction::Outbound, request.encode()?)))
+            .collect::<Result<_>>()?;
+        let inbound = |frame: &str| (Direction::Inbound, frame.to_owned());
+        let frames = [
+            requests[0].clone(),
+            requests[1].clone(),
+            inbound(r#"[1,0,0,1,"Online"]"#),
+            inbound(r#"[1,1,0,2,{}]"#),
+            inbound(r#"[3,1,"Offline"]"#),
+            requests[2].clone(),
+            inbound("[2,2,0]"),
+            requests[3].clone(),
+            inbound(r#"[1,3,0,1,"Offline"]"#),
+            inbound(r#"[3,1,"Online"]"#),
+        ];
+        for (direction, frame) in frames {
+            recorder.record(direction, &Message::Text(frame));
+        }
+        recorder.flush().await;
+
+        let xbl_auth = Arc::new(Mutex::new(XBLAuth::new(
+            "../../auth".parse()?,
+            "Ferris".into(),
+        )));
+        let client = RtaClientBuilder::new(xbl_auth).replay(&path)?;
+        std::fs::remove_file(&path)?;
+        let (handle, mut events) = client.listen()?;
+        let mut received = vec![];
+        while let Some(event) = events.recv().await {
+            received.push(event);
+        }
+        assert!(
+            matches!(
+                &received[..],
+                [
+                    RtaEvent::Subscribe {
+                        seq_id: 0,
+                        sub_id: 1,
+                        ..
+                    },
+                    RtaEvent::Subscribe {
+                        seq_id: 1,
+                        sub_id: 2,
+                        ..
+                    },
+                    RtaEvent::Event { sub_id: 1, .. },
+                    RtaEvent::Unsubscribe {
+                        seq_id: 2,
+                        sub_id: 2,
+                        ..
+                    },
+                    RtaEvent::Subscribe {
+                        seq_id: 3,
+                        sub_id: 1,
+                        ..
+                    },
+                    RtaEvent::Event { sub_id: 1, .. },
+                ]
+            ),
+            "{received:?}"
+        );
+        assert!(matches!(handle.join().await, Termination::Closed));
+        Ok(())
+    }
 
     #[tokio::test]
     async fn it_works() -> Result<()> {
@@ -215,7 +1722,7 @@ mod tests {
             .add_subscription("https://sessiondirectory.xboxlive.com/connections/".to_owned())
             .connect()
             .await?;
-        let (mut ws_writer, mut rx) = client.listen()?;
+        let (ws_writer, mut rx) = client.listen()?;
         while let Some(v) = rx.recv().await {
             println!("{v:?}");
             ws_writer.close().await?;
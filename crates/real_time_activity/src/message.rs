@@ -1,13 +1,19 @@
-use serde::{de::Visitor, Deserialize};
+use serde::{
+    de::{IgnoredAny, Visitor},
+    ser::SerializeSeq,
+    Deserialize, Serialize,
+};
+use serde_json::Value;
 
-use crate::status::Status;
+use crate::{resource::SessionRef, status::Status};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MessageType {
-    Subscribe = 1,
-    Unsubscribe = 2,
-    Event = 3,
-    Resync = 4,
+    Subscribe,
+    Unsubscribe,
+    Event,
+    Resync,
+    Unknown(i64),
 }
 impl From<i64> for MessageType {
     fn from(value: i64) -> Self {
@@ -16,37 +22,71 @@ impl From<i64> for MessageType {
             2 => Self::Unsubscribe,
             3 => Self::Event,
             4 => Self::Resync,
-            n => panic!("[<MessageType as From<u64>>::from] Invalid value: {n}."),
+            n => Self::Unknown(n),
+        }
+    }
+}
+impl MessageType {
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::Subscribe => 1,
+            Self::Unsubscribe => 2,
+            Self::Event => 3,
+            Self::Resync => 4,
+            Self::Unknown(n) => *n,
         }
     }
 }
 
-#[derive(Debug)]
+/// Frames sent by RTA.
+#[derive(Debug, Clone, PartialEq)]
 pub enum MessageData {
     Subscribe {
         seq_id: i64,
         status: Status,
-        sub_id: i64,
-        connection_id: String,
+        // Only present when the subscription succeeded.
+        sub_id: Option<i64>,
+        payload: Value,
     },
     Unsubscribe {
         seq_id: i64,
         status: Status,
     },
+    /// `data` is decoded by the resource `sub_id` subscribes to, see
+    /// `RtaResource::decode_event`.
     Event {
         sub_id: i64,
-        data: EventData,
+        data: Value,
     },
     Resync,
+    /// A message type this client does not know, with the rest of its fields.
+    Unknown {
+        msg_type: i64,
+        fields: Vec<Value>,
+    },
+}
+
+/// Frames sent by the client.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Request {
+    Subscribe { seq_id: i64, uri: String },
+    Unsubscribe { seq_id: i64, sub_id: i64 },
+}
+impl Request {
+    pub fn encode(&self) -> serde_json::Result<String> {
+        serde_json::to_string(self)
+    }
 }
 
-#[derive(Debug, Deserialize)]
+/// Body of the events of session resources: shoulder taps naming the sessions
+/// that changed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct EventData {
     pub ncid: String,
     pub shoulder_taps: Vec<EventShoulderTap>,
 }
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(rename_all = "PascalCase")]
 pub struct EventShoulderTap {
     pub timestamp: String,
@@ -56,6 +96,57 @@ pub struct EventShoulderTap {
     pub branch: String,
     pub change_number: i64,
 }
+impl EventShoulderTap {
+    /// The session that changed.
+    pub fn session(&self) -> anyhow::Result<SessionRef> {
+        SessionRef::parse(&self.resource)
+    }
+}
+
+impl Serialize for MessageData {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut seq = serializer.serialize_seq(None)?;
+        match self {
+            MessageData::Subscribe {
+                seq_id,
+                status,
+                sub_id,
+                payload,
+            } => {
+                seq.serialize_element(&MessageType::Subscribe.code())?;
+                seq.serialize_element(seq_id)?;
+                seq.serialize_element(&status.code())?;
+                if let Some(sub_id) = sub_id {
+                    seq.serialize_element(sub_id)?;
+                    seq.serialize_element(payload)?;
+                }
+            }
+            MessageData::Unsubscribe { seq_id, status } => {
+                seq.serialize_element(&MessageType::Unsubscribe.code())?;
+                seq.serialize_element(seq_id)?;
+                seq.serialize_element(&status.code())?;
+            }
+            MessageData::Event { sub_id, data } => {
+                seq.serialize_element(&MessageType::Event.code())?;
+                seq.serialize_element(sub_id)?;
+                seq.serialize_element(data)?;
+            }
+            MessageData::Resync => {
+                seq.serialize_element(&MessageType::Resync.code())?;
+            }
+            MessageData::Unknown { msg_type, fields } => {
+                seq.serialize_element(msg_type)?;
+                for field in fields {
+                    seq.serialize_element(field)?;
+                }
+            }
+        }
+        seq.end()
+    }
+}
 
 impl<'de> Deserialize<'de> for MessageData {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
@@ -67,7 +158,7 @@ impl<'de> Deserialize<'de> for MessageData {
             type Value = MessageData;
 
             fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
-                formatter.write_str("any valid JSON value")
+                formatter.write_str("an RTA message array")
             }
 
             fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
@@ -75,34 +166,28 @@ impl<'de> Deserialize<'de> for MessageData {
                 A: serde::de::SeqAccess<'de>,
             {
                 use serde::de::Error;
-                let msg_type = <MessageType as From<i64>>::from(
-                    seq.next_element()?.ok_or(Error::invalid_length(0, &self))?,
-                );
-                let ret = match msg_type {
+                let code: i64 = seq.next_element()?.ok_or(Error::invalid_length(0, &self))?;
+                let ret = match MessageType::from(code) {
                     MessageType::Subscribe => {
                         let seq_id = seq.next_element()?.ok_or(Error::invalid_length(1, &self))?;
-                        let status = seq.next_element()?.ok_or(Error::invalid_length(2, &self))?;
-                        let sub_id = seq.next_element()?.ok_or(Error::invalid_length(3, &self))?;
-                        #[derive(Debug, Deserialize)]
-                        #[allow(non_snake_case)]
-                        struct Payload {
-                            ConnectionId: String,
-                        }
-                        let Payload { ConnectionId } =
-                            seq.next_element()?.ok_or(Error::invalid_length(4, &self))?;
+                        let status: i64 =
+                            seq.next_element()?.ok_or(Error::invalid_length(2, &self))?;
+                        let sub_id = seq.next_element()?;
+                        let payload = seq.next_element()?.unwrap_or(Value::Null);
                         MessageData::Subscribe {
                             seq_id,
-                            status: <Status as From<i64>>::from(status),
+                            status: Status::from(status),
                             sub_id,
-                            connection_id: ConnectionId,
+                            payload,
                         }
                     }
                     MessageType::Unsubscribe => {
                         let seq_id = seq.next_element()?.ok_or(Error::invalid_length(1, &self))?;
-                        let status = seq.next_element()?.ok_or(Error::invalid_length(2, &self))?;
+                        let status: i64 =
+                            seq.next_element()?.ok_or(Error::invalid_length(2, &self))?;
                         MessageData::Unsubscribe {
                             seq_id,
-                            status: <Status as From<i64>>::from(status),
+                            status: Status::from(status),
                         }
                     }
                     MessageType::Event => {
@@ -111,10 +196,256 @@ impl<'de> Deserialize<'de> for MessageData {
                         MessageData::Event { sub_id, data }
                     }
                     MessageType::Resync => MessageData::Resync,
+                    MessageType::Unknown(msg_type) => {
+                        let mut fields = vec![];
+                        while let Some(field) = seq.next_element()? {
+                            fields.push(field);
+                        }
+                        MessageData::Unknown { msg_type, fields }
+                    }
                 };
+                // Tolerate fields added to known messages.
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
                 Ok(ret)
             }
         }
         deserializer.deserialize_seq(MessageVisitor)
     }
 }
+
+impl Serialize for Request {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut seq = serializer.serialize_seq(Some(3))?;
+        match self {
+            Request::Subscribe { seq_id, uri } => {
+                seq.serialize_element(&MessageType::Subscribe.code())?;
+                seq.serialize_element(seq_id)?;
+                seq.serialize_element(uri)?;
+            }
+            Request::Unsubscribe { seq_id, sub_id } => {
+                seq.serialize_element(&MessageType::Unsubscribe.code())?;
+                seq.serialize_element(seq_id)?;
+                seq.serialize_element(sub_id)?;
+            }
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Request {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct RequestVisitor;
+        impl<'de> Visitor<'de> for RequestVisitor {
+            type Value = Request;
+
+            fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                formatter.write_str("an RTA request array")
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::SeqAccess<'de>,
+            {
+                use serde::de::{Error, Unexpected};
+                let msg_type: i64 = seq.next_element()?.ok_or(Error::invalid_length(0, &self))?;
+                let seq_id = seq.next_element()?.ok_or(Error::invalid_length(1, &self))?;
+                let ret = match MessageType::from(msg_type) {
+                    MessageType::Subscribe => Request::Subscribe {
+                        seq_id,
+                        uri: seq.next_element()?.ok_or(Error::invalid_length(2, &self))?,
+                    },
+                    MessageType::Unsubscribe => Request::Unsubscribe {
+                        seq_id,
+                        sub_id: seq.next_element()?.ok_or(Error::invalid_length(2, &self))?,
+                    },
+                    _ => {
+                        return Err(Error::invalid_value(
+                            Unexpected::Signed(msg_type),
+                            &"a subscribe or unsubscribe request",
+                        ))
+                    }
+                };
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+                Ok(ret)
+            }
+        }
+        deserializer.deserialize_seq(RequestVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::{json, Value};
+
+    use super::{MessageData, MessageType, Request};
+    use crate::status::Status;
+
+    // Every message type, including failures and a type this client does not
+    // know.
+    const FRAMES: &[&str] = &[
+        r#"[1,0,0,1,{"ConnectionId":"3c8d7d3b-4fa3-4d2a-9c6e-b2b1f1a0d6e4"}]"#,
+        r#"[1,1,0,2,"Online"]"#,
+        r#"[1,2,1]"#,
+        r#"[1,3,1003]"#,
+        r#"[2,4,0]"#,
+        r#"[3,1,{"Ncid":"fd4b0d4e-5b2c-4b46-a7c1-0d9a7a2bc7e0","ShoulderTaps":[{"Timestamp":"2024-08-20T12:34:56.789Z","Subscription":"0a0b0c0d","ResourceType":"MultiplayerSessionDirectory","Resource":"4fc10100~MinecraftLobby~5a1bf1a5","Branch":"b8e3c2a0","ChangeNumber":7}]}]"#,
+        r#"[3,2,"Offline"]"#,
+        r#"[4]"#,
+        r#"[9,1,"future"]"#,
+    ];
+
+    // Every message type as the service sends it for the resources this client
+    // subscribes to, rather than reduced to the fields that matter.
+    const SERVICE_FRAMES: &[&str] = &[
+        r#"[1,1,0,1,{"ConnectionId":"6f2c7a9e-1b4d-4c3a-8e5f-0d2b9c7a4e11"}]"#,
+        r#"[1,2,0,2,{"xuid":"2535400000000000","state":"Online","devices":[{"type":"WindowsOneCore","titles":[{"id":"1739947436","name":"Minecraft","placement":"Full","state":"Active","lastModified":"2024-08-20T12:30:00.1234567Z"}]}]}]"#,
+        r#"[1,3,1001]"#,
+        r#"[1,4,1002]"#,
+        r#"[2,5,0]"#,
+        r#"[2,6,1]"#,
+        r#"[3,1,{"Ncid":"9f8e7d6c-5b4a-4392-8170-6f5e4d3c2b1a","ShoulderTaps":[{"Timestamp":"2024-08-20T12:34:56.7890123Z","Subscription":"8d5fe2e4-7c3a-4b1e-9a0f-3e6d2c1b0a98","ResourceType":"MultiplayerSessionDirectory","Resource":"4fc10100-5f7a-4470-899b-280835760c07~MinecraftLobby~5a1bf1a5-0c3b-4d8e-9f2a-7b6c5d4e3f21","Branch":"2b5a0c1e-9d8f-4e7a-b6c5-d4e3f2a1b0c9","ChangeNumber":12}]}]"#,
+        r#"[3,2,"Offline"]"#,
+        r#"[3,3,{"NotificationType":"Added","Xuids":["2535400000000001","2535400000000002"]}]"#,
+        r#"[4]"#,
+    ];
+
+    #[test]
+    fn service_frames() -> Result<()> {
+        let types: Vec<_> = SERVICE_FRAMES
+            .iter()
+            .map(|frame| Ok(serde_json::from_str::<MessageData>(frame)?))
+            .collect::<Result<_>>()?;
+        assert!(matches!(
+            &types[..],
+            [
+                MessageData::Subscribe {
+                    sub_id: Some(1),
+                    status: Status::Success,
+                    ..
+                },
+                MessageData::Subscribe {
+                    sub_id: Some(2),
+                    status: Status::Success,
+                    ..
+                },
+                MessageData::Subscribe {
+                    status: Status::Throttled,
+                    ..
+                },
+                MessageData::Subscribe {
+                    status: Status::ServiceUnavailable,
+                    ..
+                },
+                MessageData::Unsubscribe {
+                    seq_id: 5,
+                    status: Status::Success
+                },
+                MessageData::Unsubscribe {
+                    status: Status::UnknownResource,
+                    ..
+                },
+                MessageData::Event { sub_id: 1, .. },
+                MessageData::Event { sub_id: 2, .. },
+                MessageData::Event { sub_id: 3, .. },
+                MessageData::Resync,
+            ]
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip() -> Result<()> {
+        for frame in FRAMES.iter().chain(SERVICE_FRAMES) {
+            let message: MessageData = serde_json::from_str(frame)?;
+            let encoded = serde_json::to_string(&message)?;
+            assert_eq!(
+                serde_json::from_str::<Value>(&encoded)?,
+                serde_json::from_str::<Value>(frame)?,
+                "{frame}"
+            );
+            assert_eq!(serde_json::from_str::<MessageData>(&encoded)?, message);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn unknown_values() -> Result<()> {
+        let message: MessageData = serde_json::from_str(FRAMES[3])?;
+        assert!(matches!(
+            message,
+            MessageData::Subscribe {
+                status: Status::Unknown(1003),
+                sub_id: None,
+                ..
+            }
+        ));
+        let message: MessageData = serde_json::from_str(FRAMES[8])?;
+        assert_eq!(
+            message,
+            MessageData::Unknown {
+                msg_type: 9,
+                fields: vec![json!(1), json!("future")],
+            }
+        );
+        assert_eq!(MessageType::from(9), MessageType::Unknown(9));
+        assert_eq!(Status::from(1003), Status::Unknown(1003));
+        assert_eq!(Status::from(2), Status::SubscriptionLimitReached);
+        let message: MessageData = serde_json::from_str(r#"[2,5,0,"extra"]"#)?;
+        assert_eq!(
+            message,
+            MessageData::Unsubscribe {
+                seq_id: 5,
+                status: Status::Success
+            }
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn malformed() {
+        for frame in [
+            r#"[]"#,
+            r#"["1"]"#,
+            r#"[1,0]"#,
+            r#"[3,1]"#,
+            r#"[3,"1",{}]"#,
+            r#"{}"#,
+        ] {
+            assert!(
+                serde_json::from_str::<MessageData>(frame).is_err(),
+                "{frame}"
+            );
+        }
+    }
+
+    #[test]
+    fn requests() -> Result<()> {
+        let subscribe = Request::Subscribe {
+            seq_id: 3,
+            uri: r#"https://example.com/"quoted""#.into(),
+        };
+        assert_eq!(
+            subscribe.encode()?,
+            r#"[1,3,"https://example.com/\"quoted\""]"#
+        );
+        let unsubscribe = Request::Unsubscribe {
+            seq_id: 4,
+            sub_id: 2,
+        };
+        assert_eq!(unsubscribe.encode()?, "[2,4,2]");
+        for request in [subscribe, unsubscribe] {
+            assert_eq!(
+                serde_json::from_str::<Request>(&request.encode()?)?,
+                request
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,433 @@
+//! A local stand-in for `rta.xboxlive.com`, for tests.
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use anyhow::Result;
+use futures_util::{SinkExt, StreamExt};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+    sync::{mpsc, watch},
+    task::JoinHandle,
+};
+use tokio_tungstenite::{
+    accept_hdr_async,
+    tungstenite::{
+        handshake::server::{ErrorResponse, Request as HandshakeRequest, Response},
+        http::{HeaderValue, StatusCode},
+        Message,
+    },
+};
+use xbl_auth::{cache::Cache, expire::Expire, request_token::XSTSToken, XBLAuth};
+
+use crate::{
+    message::{MessageData, Request},
+    status::Status,
+};
+
+const SUB_PROTOCOL: &str = "rta.xboxlive.com.V2";
+
+/// How the mock answers a subscribe request.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Reply {
+    pub status: Status,
+    /// Sent along with a successful answer.
+    pub payload: Value,
+}
+
+impl Reply {
+    pub fn success(payload: Value) -> Self {
+        Self {
+            status: Status::Success,
+            payload,
+        }
+    }
+
+    pub fn status(status: Status) -> Self {
+        Self {
+            status,
+            payload: Value::Null,
+        }
+    }
+}
+
+enum Command {
+    Send(Message),
+    /// Drops the socket without a close frame.
+    Drop,
+}
+
+struct MockConnection {
+    id: usize,
+    commands: mpsc::UnboundedSender<Command>,
+    // sub_id -> uri
+    subscriptions: HashMap<i64, String>,
+}
+
+#[derive(Default)]
+struct State {
+    replies: HashMap<String, Reply>,
+    one_shot_replies: HashMap<String, VecDeque<Reply>>,
+    unsubscribe_status: Option<Status>,
+    subscription_limit: Option<usize>,
+    connections: Vec<MockConnection>,
+    next_sub_id: i64,
+    accepted: usize,
+    authorizations: Vec<String>,
+    requests: Vec<Request>,
+}
+
+impl State {
+    fn reply(&mut self, uri: &str) -> Reply {
+        let one_shot = self
+            .one_shot_replies
+            .get_mut(uri)
+            .and_then(VecDeque::pop_front);
+        one_shot
+            .or_else(|| self.replies.get(uri).cloned())
+            .unwrap_or_else(|| Reply::success(Value::Null))
+    }
+
+    fn connection(&mut self, id: usize) -> Option<&mut MockConnection> {
+        self.connections.iter_mut().find(|c| c.id == id)
+    }
+}
+
+/// An RTA server on localhost speaking the `rta.xboxlive.com.V2` subprotocol.
+///
+/// Subscriptions succeed with a `null` payload unless told otherwise, and every
+/// connection is accepted whatever its `authorization` header.
+pub struct MockRta {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    silent: watch::Sender<bool>,
+    task: JoinHandle<()>,
+}
+
+impl MockRta {
+    pub async fn start() -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let state = Arc::new(Mutex::new(State {
+            next_sub_id: 1,
+            ..Default::default()
+        }));
+        let silent = watch::Sender::new(false);
+        let task = tokio::spawn(accept(listener, state.clone(), silent.clone()));
+        Ok(Self {
+            addr,
+            state,
+            silent,
+            task,
+        })
+    }
+
+    /// What to pass to `RtaClientBuilder::set_uri`.
+    pub fn uri(&self) -> String {
+        format!("ws://{}/connect", self.addr)
+    }
+
+    /// Answers every later subscribe request for `uri` with `reply`.
+    pub fn set_reply(&self, uri: &str, reply: Reply) -> &Self {
+        self.state().replies.insert(uri.to_owned(), reply);
+        self
+    }
+
+    /// Answers the next subscribe request for `uri` with `reply`, before
+    /// falling back to [`MockRta::set_reply`].
+    pub fn push_reply(&self, uri: &str, reply: Reply) -> &Self {
+        let mut state = self.state();
+        let replies = state.one_shot_replies.entry(uri.to_owned()).or_default();
+        replies.push_back(reply);
+        self
+    }
+
+    /// Refuses unsubscribe requests with `status`.
+    pub fn set_unsubscribe_status(&self, status: Status) -> &Self {
+        self.state().unsubscribe_status = Some(status);
+        self
+    }
+
+    /// Refuses subscriptions beyond `limit` per connection with
+    /// `SubscriptionLimitReached`.
+    pub fn set_subscription_limit(&self, limit: usize) -> &Self {
+        self.state().subscription_limit = Some(limit);
+        self
+    }
+
+    /// Sends an event to every subscription of `uri`, returning how many there
+    /// were.
+    pub fn event(&self, uri: &str, data: Value) -> usize {
+        let state = self.state();
+        let mut sent = 0;
+        for connection in &state.connections {
+            for (sub_id, _) in connection.subscriptions.iter().filter(|(_, u)| *u == uri) {
+                let event = MessageData::Event {
+                    sub_id: *sub_id,
+                    data: data.clone(),
+                };
+                let frame = serde_json::to_string(&event).unwrap();
+                let _ = connection
+                    .commands
+                    .send(Command::Send(Message::Text(frame)));
+                sent += 1;
+            }
+        }
+        sent
+    }
+
+    /// Tells every connection it may have missed events.
+    pub fn resync(&self) {
+        let frame = serde_json::to_string(&MessageData::Resync).unwrap();
+        self.broadcast(Message::Text(frame));
+    }
+
+    /// Sends `message` as is on every connection.
+    pub fn broadcast(&self, message: Message) {
+        for connection in &self.state().connections {
+            let _ = connection.commands.send(Command::Send(message.clone()));
+        }
+    }
+
+    /// Drops every connection without closing it, as a network failure would.
+    pub fn disconnect(&self) {
+        for connection in self.state().connections.drain(..) {
+            let _ = connection.commands.send(Command::Drop);
+        }
+    }
+
+    /// While silent, nothing is read from the connections, so requests and
+    /// pings go unanswered.
+    pub fn set_silent(&self, silent: bool) {
+        self.silent.send_replace(silent);
+    }
+
+    /// Serves a connection on `stream` instead of one accepted over TCP.
+    pub fn serve<S>(&self, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        tokio::spawn(serve(stream, self.state.clone(), self.silent.subscribe()));
+    }
+
+    /// Connections accepted so far.
+    pub fn accepted(&self) -> usize {
+        self.state().accepted
+    }
+
+    /// Connections currently open.
+    pub fn connections(&self) -> usize {
+        self.state().connections.len()
+    }
+
+    /// URIs subscribed across the open connections.
+    pub fn subscriptions(&self) -> Vec<String> {
+        let state = self.state();
+        let uris = state
+            .connections
+            .iter()
+            .flat_map(|c| c.subscriptions.values());
+        uris.cloned().collect()
+    }
+
+    /// Every request received so far, oldest first.
+    pub fn requests(&self) -> Vec<Request> {
+        self.state().requests.clone()
+    }
+
+    /// The `authorization` header of every connection accepted so far.
+    pub fn authorizations(&self) -> Vec<String> {
+        self.state().authorizations.clone()
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+}
+
+impl fmt::Debug for MockRta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MockRta")
+            .field("addr", &self.addr)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for MockRta {
+    fn drop(&mut self) {
+        self.task.abort();
+        self.disconnect();
+    }
+}
+
+async fn accept(listener: TcpListener, state: Arc<Mutex<State>>, silent: watch::Sender<bool>) {
+    while let Ok((stream, _)) = listener.accept().await {
+        tokio::spawn(serve(stream, state.clone(), silent.subscribe()));
+    }
+}
+
+async fn serve<S>(stream: S, state: Arc<Mutex<State>>, mut silent: watch::Receiver<bool>)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut authorization = None;
+    // The error type is set by tungstenite.
+    #[allow(clippy::result_large_err)]
+    let handshake = |request: &HandshakeRequest, mut response: Response| {
+        let headers = request.headers();
+        let offers_protocol = headers
+            .get_all("sec-websocket-protocol")
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.split(',').any(|p| p.trim() == SUB_PROTOCOL));
+        if !offers_protocol {
+            let mut error = ErrorResponse::new(Some(format!("Expected {SUB_PROTOCOL}.")));
+            *error.status_mut() = StatusCode::BAD_REQUEST;
+            return Err(error);
+        }
+        authorization = headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let protocol = HeaderValue::from_static(SUB_PROTOCOL);
+        response
+            .headers_mut()
+            .insert("sec-websocket-protocol", protocol);
+        Ok(response)
+    };
+    let Ok(socket) = accept_hdr_async(stream, handshake).await else {
+        return;
+    };
+    let (mut writer, mut reader) = socket.split();
+    let (commands, mut rx) = mpsc::unbounded_channel();
+    let id = {
+        let mut state = state.lock().unwrap();
+        let id = state.accepted;
+        state.accepted += 1;
+        state.authorizations.extend(authorization);
+        state.connections.push(MockConnection {
+            id,
+            commands,
+            subscriptions: HashMap::new(),
+        });
+        id
+    };
+    loop {
+        let reading = !*silent.borrow_and_update();
+        let frame = tokio::select! {
+            command = rx.recv() => match command {
+                Some(Command::Send(message)) => {
+                    if writer.send(message).await.is_err() {
+                        break;
+                    }
+                    continue;
+                }
+                Some(Command::Drop) | None => break,
+            },
+            changed = silent.changed() => {
+                if changed.is_err() {
+                    break;
+                }
+                continue;
+            }
+            frame = reader.next(), if reading => frame,
+        };
+        let text = match frame {
+            Some(Ok(Message::Text(text))) => text,
+            Some(Ok(Message::Close(_))) => {
+                let _ = writer.send(Message::Close(None)).await;
+                break;
+            }
+            Some(Ok(_)) => continue,
+            Some(Err(_)) | None => break,
+        };
+        let Ok(request) = serde_json::from_str::<Request>(&text) else {
+            continue;
+        };
+        let answer = answer(&mut state.lock().unwrap(), id, request);
+        let frame = serde_json::to_string(&answer).unwrap();
+        if writer.send(Message::Text(frame)).await.is_err() {
+            break;
+        }
+    }
+    state.lock().unwrap().connections.retain(|c| c.id != id);
+}
+
+fn answer(state: &mut State, id: usize, request: Request) -> MessageData {
+    state.requests.push(request.clone());
+    match request {
+        Request::Subscribe { seq_id, uri } => {
+            let limit = state.subscription_limit;
+            let full = state
+                .connection(id)
+                .is_some_and(|c| limit.is_some_and(|limit| c.subscriptions.len() >= limit));
+            let reply = match full {
+                true => Reply::status(Status::SubscriptionLimitReached),
+                false => state.reply(&uri),
+            };
+            if reply.status != Status::Success {
+                return MessageData::Subscribe {
+                    seq_id,
+                    status: reply.status,
+                    sub_id: None,
+                    payload: Value::Null,
+                };
+            }
+            let sub_id = state.next_sub_id;
+            state.next_sub_id += 1;
+            if let Some(connection) = state.connection(id) {
+                connection.subscriptions.insert(sub_id, uri);
+            }
+            MessageData::Subscribe {
+                seq_id,
+                status: Status::Success,
+                sub_id: Some(sub_id),
+                payload: reply.payload,
+            }
+        }
+        Request::Unsubscribe { seq_id, sub_id } => {
+            let status = state.unsubscribe_status.unwrap_or(Status::Success);
+            if status == Status::Success {
+                if let Some(connection) = state.connection(id) {
+                    connection.subscriptions.remove(&sub_id);
+                }
+            }
+            MessageData::Unsubscribe { seq_id, status }
+        }
+    }
+}
+
+/// An `XBLAuth` whose cache holds an Xbox Live token valid for an hour, so
+/// that connecting to a [`MockRta`] needs no sign-in.
+pub async fn xbl_auth() -> Result<Arc<tokio::sync::Mutex<XBLAuth>>> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let dir = format!(
+        "rta-mock-{}-{}",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::SeqCst)
+    );
+    let path: PathBuf = std::env::temp_dir().join(dir);
+    let user_name = "Ferris";
+    let token = XSTSToken {
+        gamer_tag: user_name.to_owned(),
+        xuid: "2535400000000000".to_owned(),
+        user_hash: "1234567890".to_owned(),
+        token: "mock".to_owned(),
+    };
+    let cache = Cache::new(path.clone(), user_name);
+    cache
+        .update_xsts(&Expire::with_duration(token, 3600))
+        .await?;
+    let xbl_auth = XBLAuth::new(path, user_name.to_owned());
+    Ok(Arc::new(tokio::sync::Mutex::new(xbl_auth)))
+}
@@ -0,0 +1,440 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use tokio::sync::{mpsc, Mutex};
+use tracing::{info, warn, Instrument};
+
+use crate::{
+    builder::RtaClientBuilder,
+    bus::{EventBus, EventFilter, EventSource, EventStream},
+    error::RtaError,
+    event::RtaEvent,
+    handle::{RtaHandle, Termination},
+    resource::RtaResource,
+    status::Status,
+    subscription::{ActiveSubscription, Subscription},
+    Overflowed, WSWriter,
+};
+
+#[derive(Debug)]
+struct PoolConnection {
+    handle: RtaHandle,
+    // Number of subscriptions the connection had when RTA refused another one.
+    limit: Option<usize>,
+}
+
+impl PoolConnection {
+    fn is_full(&self) -> bool {
+        self.limit
+            .is_some_and(|limit| self.handle.subscriptions().len() >= limit)
+    }
+}
+
+/// Several RTA connections behind one subscription API, opening another
+/// connection whenever the open ones reach RTA's subscription limit.
+///
+/// Events of every connection go to the same streams, and subscription keys
+/// are unique across connections. A subscription a connection cannot take back
+/// after reconnecting, or whose connection gave up, moves to another one,
+/// keeping its key.
+pub struct RtaPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    builder: RtaClientBuilder,
+    bus: EventBus,
+    // Never held across an await.
+    connections: std::sync::Mutex<Vec<PoolConnection>>,
+    // Held while a connection opens, so that callers out of room open one
+    // between them.
+    opening: Mutex<()>,
+    opened: AtomicUsize,
+    overflow: mpsc::UnboundedSender<Overflowed>,
+    // Taken by the task moving overflowed subscriptions, started by the first
+    // subscribe.
+    overflowed: std::sync::Mutex<Option<mpsc::UnboundedReceiver<Overflowed>>>,
+    // Whether the pool let go of its hold on `bus`.
+    released: AtomicBool,
+}
+
+impl RtaPool {
+    /// Connections are opened with `builder`, the first one when it is needed.
+    pub fn new(builder: RtaClientBuilder) -> Self {
+        let bus = builder.event_bus();
+        // Keeps the streams open while connections come and go.
+        bus.attach();
+        let (overflow, overflowed) = mpsc::unbounded_channel();
+        let inner = Inner {
+            bus,
+            builder,
+            connections: Default::default(),
+            opening: Mutex::new(()),
+            opened: AtomicUsize::new(0),
+            overflow,
+            overflowed: std::sync::Mutex::new(Some(overflowed)),
+            released: AtomicBool::new(false),
+        };
+        Self {
+            inner: Arc::new(inner),
+        }
+    }
+
+    /// A new consumer of the events of every connection.
+    pub fn events(&self, filter: EventFilter) -> EventStream {
+        self.inner.bus.subscribe(filter)
+    }
+
+    pub async fn subscribe(&self, uri: &str) -> Result<Subscription, RtaError> {
+        let overflowed = self.inner.overflowed.lock().unwrap().take();
+        if let Some(overflowed) = overflowed {
+            let pool = Arc::downgrade(&self.inner);
+            tokio::spawn(move_overflowed(pool, overflowed).in_current_span());
+        }
+        loop {
+            let writer = self.inner.writer().await?;
+            match writer.subscribe(uri).await {
+                // Unless even an empty connection refuses it.
+                Err(RtaError::Status(Status::SubscriptionLimitReached))
+                    if self.inner.set_full(&writer) => {}
+                result => return result,
+            }
+        }
+    }
+
+    #[inline]
+    pub async fn subscribe_resource(
+        &self,
+        resource: &RtaResource,
+    ) -> Result<Subscription, RtaError> {
+        self.subscribe(&resource.uri()).await
+    }
+
+    pub fn subscriptions(&self) -> Vec<ActiveSubscription> {
+        let connections = self.inner.connections.lock().unwrap();
+        connections
+            .iter()
+            .flat_map(|connection| connection.handle.subscriptions())
+            .collect()
+    }
+
+    /// Connections still running.
+    pub fn connection_count(&self) -> usize {
+        let connections = self.inner.connections.lock().unwrap();
+        connections
+            .iter()
+            .filter(|c| !c.handle.is_finished())
+            .count()
+    }
+
+    /// Closes every connection.
+    pub async fn close(&self) -> Result<()> {
+        let connections = std::mem::take(&mut *self.inner.connections.lock().unwrap());
+        self.inner.release();
+        for connection in connections {
+            connection.handle.close().await?;
+        }
+        Ok(())
+    }
+
+    /// Shuts every connection down as [`RtaHandle::shutdown`] does, returning
+    /// why each one stopped.
+    pub async fn shutdown(&self, timeout: Duration) -> Vec<Termination> {
+        let connections = std::mem::take(&mut *self.inner.connections.lock().unwrap());
+        self.inner.release();
+        let shutdowns = connections
+            .into_iter()
+            .map(|connection| connection.handle.shutdown(timeout));
+        futures_util::future::join_all(shutdowns).await
+    }
+}
+
+impl Drop for RtaPool {
+    fn drop(&mut self) {
+        self.inner.release();
+    }
+}
+
+impl Inner {
+    /// Lets the streams end once the last connection stops.
+    fn release(&self) {
+        if !self.released.swap(true, Ordering::SeqCst) {
+            self.bus.detach();
+        }
+    }
+
+    /// A running connection with room for another subscription.
+    fn available(&self) -> Option<WSWriter> {
+        let mut connections = self.connections.lock().unwrap();
+        // Stopped connections handed their subscriptions to `overflow`.
+        connections.retain(|connection| !connection.handle.is_finished());
+        let connection = connections.iter().find(|connection| !connection.is_full());
+        connection.map(|connection| connection.handle.writer().clone())
+    }
+
+    /// Marks the connection of `writer` as full, returning whether it holds
+    /// any subscription.
+    fn set_full(&self, writer: &WSWriter) -> bool {
+        let mut connections = self.connections.lock().unwrap();
+        let Some(connection) = connections
+            .iter_mut()
+            .find(|connection| connection.handle.writer().same_connection(writer))
+        else {
+            return false;
+        };
+        let count = connection.handle.subscriptions().len();
+        connection.limit = Some(count);
+        count > 0
+    }
+
+    /// A connection with room for another subscription, opening one if every
+    /// running connection is full.
+    async fn writer(self: &Arc<Self>) -> Result<WSWriter, RtaError> {
+        if let Some(writer) = self.available() {
+            return Ok(writer);
+        }
+        let _opening = self.opening.lock().await;
+        // Another caller may have opened one meanwhile.
+        if let Some(writer) = self.available() {
+            return Ok(writer);
+        }
+        let builder = match self.opened.fetch_add(1, Ordering::SeqCst) {
+            0 => self.builder.clone(),
+            _ => self.builder.clone().clear_subscriptions(),
+        };
+        let client = builder
+            .connect_to(self.bus.clone())
+            .await
+            .map_err(RtaError::Connect)?;
+        let (handle, _) = client
+            .set_overflow(self.overflow.clone())
+            .listen()
+            .map_err(RtaError::Connect)?;
+        let writer = handle.writer().clone();
+        let mut connections = self.connections.lock().unwrap();
+        info!(
+            connection = connections.len(),
+            "opened pooled RTA connection"
+        );
+        connections.push(PoolConnection {
+            handle,
+            limit: None,
+        });
+        Ok(writer)
+    }
+
+    /// Subscribes `overflowed` again on a connection with room for it.
+    async fn place(self: &Arc<Self>, overflowed: Overflowed) {
+        let Overflowed {
+            seq_id,
+            key,
+            uri,
+            from,
+        } = overflowed;
+        self.set_full(&from);
+        let writer = match self.writer().await {
+            Ok(writer) => writer,
+            Err(e) => {
+                warn!(error = %e, uri, "failed to move subscription");
+                let source = EventSource {
+                    key,
+                    kind: RtaResource::parse(&uri).map(|resource| resource.kind()),
+                };
+                let event = RtaEvent::SubscribeFailed {
+                    seq_id,
+                    uri,
+                    status: Status::SubscriptionLimitReached,
+                };
+                self.bus.publish(Some(source), event).await;
+                return;
+            }
+        };
+        // Registered either way, so a reconnect would replay it.
+        if let Err(e) = writer.adopt(key, &uri, &from).await {
+            warn!(error = %e, uri, "failed to request moved subscription");
+        }
+    }
+}
+
+async fn move_overflowed(pool: Weak<Inner>, mut overflowed: mpsc::UnboundedReceiver<Overflowed>) {
+    while let Some(subscription) = overflowed.recv().await {
+        let Some(pool) = pool.upgrade() else {
+            return;
+        };
+        pool.place(subscription).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, time::Duration};
+
+    use anyhow::{Context, Result};
+    use serde_json::json;
+
+    use super::RtaPool;
+    use crate::{
+        backoff::Backoff,
+        builder::RtaClientBuilder,
+        bus::EventFilter,
+        event::RtaEvent,
+        handle::Termination,
+        mock::{self, MockRta},
+        resource::RtaResource,
+        subscription::Subscription,
+    };
+
+    async fn builder(mock: &MockRta, max_attempts: Option<u32>) -> Result<RtaClientBuilder> {
+        let reconnect = Backoff {
+            initial: Duration::from_millis(10),
+            max_attempts,
+            ..Default::default()
+        };
+        let builder = RtaClientBuilder::new(mock::xbl_auth().await?)
+            .set_uri(mock.uri())
+            .set_reconnect_backoff(reconnect);
+        Ok(builder)
+    }
+
+    /// Waits for the next event of `subscription`.
+    async fn next_event(subscription: &mut Subscription) -> Result<()> {
+        tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match subscription.recv().await {
+                    Some(RtaEvent::Event { .. }) => return Ok(()),
+                    Some(_) => continue,
+                    None => anyhow::bail!("Subscription ended."),
+                }
+            }
+        })
+        .await
+        .context("No event.")?
+    }
+
+    #[tokio::test]
+    async fn shards_past_the_subscription_limit() -> Result<()> {
+        let mock = MockRta::start().await?;
+        mock.set_subscription_limit(2);
+        let builder = RtaClientBuilder::new(mock::xbl_auth().await?).set_uri(mock.uri());
+        let pool = RtaPool::new(builder);
+        let mut subscriptions = vec![];
+        for xuid in 0..5 {
+            let resource = RtaResource::presence(xuid.to_string());
+            subscriptions.push(pool.subscribe_resource(&resource).await?);
+        }
+        assert_eq!(pool.connection_count(), 3);
+        assert_eq!(mock.connections(), 3);
+        assert_eq!(pool.subscriptions().len(), 5);
+        let keys: HashSet<_> = subscriptions.iter().map(Subscription::key).collect();
+        assert_eq!(keys.len(), 5);
+
+        let terminations = pool.shutdown(Duration::from_secs(5)).await;
+        assert!(terminations
+            .iter()
+            .all(|termination| matches!(termination, Termination::Closed)));
+        assert!(mock.subscriptions().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn moves_subscriptions_refused_after_reconnecting() -> Result<()> {
+        let mock = MockRta::start().await?;
+        mock.set_subscription_limit(2);
+        let pool = RtaPool::new(builder(&mock, None).await?);
+        let mut subscriptions = vec![];
+        for xuid in 0..3 {
+            let resource = RtaResource::presence(xuid.to_string());
+            subscriptions.push(pool.subscribe_resource(&resource).await?);
+        }
+        assert_eq!(pool.connection_count(), 2);
+
+        // Both connections come back with room for one subscription only.
+        mock.set_subscription_limit(1);
+        mock.disconnect();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while mock.subscriptions().len() < 3 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("Subscriptions were not moved.")?;
+        assert_eq!(mock.connections(), 3);
+        assert_eq!(pool.connection_count(), 3);
+
+        let data = json!("Online");
+        for subscription in &mut subscriptions {
+            assert!(subscription.sub_id().is_some());
+            assert_eq!(mock.event(subscription.uri(), data.clone()), 1);
+            next_event(subscription).await?;
+        }
+        for subscription in subscriptions {
+            subscription.close().await?;
+        }
+        assert!(mock.subscriptions().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn moves_subscriptions_of_stopped_connections() -> Result<()> {
+        let mock = MockRta::start().await?;
+        // Connections give up as soon as they drop.
+        let pool = RtaPool::new(builder(&mock, Some(0)).await?);
+        let mut subscription = pool.subscribe_resource(&RtaResource::presence("0")).await?;
+        mock.disconnect();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while mock.accepted() < 2 || mock.subscriptions().is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("Subscription was not moved.")?;
+        assert_eq!(pool.connection_count(), 1);
+        assert_eq!(pool.subscriptions().len(), 1);
+
+        assert_eq!(mock.event(subscription.uri(), json!("Offline")), 1);
+        next_event(&mut subscription).await?;
+        subscription.close().await?;
+        assert!(mock.subscriptions().is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn keeps_streams_open_across_connections() -> Result<()> {
+        let mock = MockRta::start().await?;
+        // Connections give up as soon as they drop.
+        let pool = RtaPool::new(builder(&mock, Some(0)).await?);
+        let mut events = pool.events(EventFilter::default().without_connection_events());
+        let first = pool.subscribe_resource(&RtaResource::presence("0")).await?;
+        first.close().await?;
+        mock.disconnect();
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while pool.connection_count() > 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .context("Connection did not stop.")?;
+
+        let uri = RtaResource::presence("1").uri();
+        let _second = pool.subscribe(&uri).await?;
+        assert_eq!(mock.event(&uri, json!("Offline")), 1);
+        let received = tokio::time::timeout(Duration::from_secs(5), async {
+            loop {
+                match events.recv().await {
+                    Some(RtaEvent::Event { .. }) => return true,
+                    Some(_) => continue,
+                    None => return false,
+                }
+            }
+        })
+        .await?;
+        assert!(received, "events ended with the first connection");
+        Ok(())
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader, LineWriter, Write},
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc, oneshot};
+use tokio_tungstenite::tungstenite::{
+    protocol::{frame::coding::CloseCode, CloseFrame},
+    Message,
+};
+use tracing::warn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    /// Sent by RTA.
+    Inbound,
+    /// Sent by the client.
+    Outbound,
+}
+
+/// A websocket frame as it is stored in a recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", content = "data", rename_all = "lowercase")]
+pub enum Frame {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<(u16, String)>),
+}
+
+impl Frame {
+    /// `None` for raw frames, which are never read from a websocket.
+    pub fn from_message(message: &Message) -> Option<Self> {
+        let frame = match message {
+            Message::Text(text) => Self::Text(text.clone()),
+            Message::Binary(data) => Self::Binary(data.clone()),
+            Message::Ping(data) => Self::Ping(data.clone()),
+            Message::Pong(data) => Self::Pong(data.clone()),
+            Message::Close(close) => Self::Close(
+                close
+                    .as_ref()
+                    .map(|close| (close.code.into(), close.reason.to_string())),
+            ),
+            Message::Frame(_) => return None,
+        };
+        Some(frame)
+    }
+
+    pub fn into_message(self) -> Message {
+        match self {
+            Self::Text(text) => Message::Text(text),
+            Self::Binary(data) => Message::Binary(data),
+            Self::Ping(data) => Message::Ping(data),
+            Self::Pong(data) => Message::Pong(data),
+            Self::Close(close) => Message::Close(close.map(|(code, reason)| CloseFrame {
+                code: CloseCode::from(code),
+                reason: reason.into(),
+            })),
+        }
+    }
+}
+
+/// One line of a recording.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedFrame {
+    /// Milliseconds since the Unix epoch, for reading only: replays do not wait
+    /// for it.
+    pub at: u64,
+    pub direction: Direction,
+    pub frame: Frame,
+}
+
+#[derive(Debug)]
+enum Command {
+    Write(RecordedFrame),
+    Flush(oneshot::Sender<()>),
+}
+
+/// Writes every frame of the connections it is given to a JSON-lines file.
+///
+/// Clones append to the same file, so one recorder can follow a client across
+/// reconnects. The file is written on a thread of its own, which stops once
+/// every clone is dropped; recording never waits for it.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+    commands: mpsc::UnboundedSender<Command>,
+}
+
+impl Recorder {
+    /// Starts a new recording at `path`, replacing any file already there.
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file = File::create(path)
+            .with_context(|| format!("Failed to create recording {}.", path.display()))?;
+        let (commands, received) = mpsc::unbounded_channel();
+        std::thread::Builder::new()
+            .name("rta-recorder".into())
+            .spawn(move || write_frames(LineWriter::new(file), received))
+            .context("Failed to start the recorder.")?;
+        Ok(Self { commands })
+    }
+
+    pub fn record(&self, direction: Direction, message: &Message) {
+        let Some(frame) = Frame::from_message(message) else {
+            return;
+        };
+        let at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let record = RecordedFrame {
+            at,
+            direction,
+            frame,
+        };
+        let _ = self.commands.send(Command::Write(record));
+    }
+
+    /// Waits until every frame recorded so far is in the file.
+    pub async fn flush(&self) {
+        let (tx, rx) = oneshot::channel();
+        if self.commands.send(Command::Flush(tx)).is_ok() {
+            let _ = rx.await;
+        }
+    }
+}
+
+fn write_frames(mut file: LineWriter<File>, mut commands: mpsc::UnboundedReceiver<Command>) {
+    while let Some(command) = commands.blocking_recv() {
+        match command {
+            Command::Write(record) => {
+                // A broken recording must not take the connection down with it.
+                if let Err(e) = write_frame(&mut file, &record) {
+                    warn!(error = format!("{e:#}"), "recording failed");
+                }
+            }
+            Command::Flush(done) => {
+                if let Err(e) = file.flush() {
+                    warn!(error = %e, "recording failed");
+                }
+                let _ = done.send(());
+            }
+        }
+    }
+}
+
+fn write_frame(file: &mut LineWriter<File>, record: &RecordedFrame) -> Result<()> {
+    serde_json::to_writer(&mut *file, record)?;
+    file.write_all(b"\n")?;
+    Ok(())
+}
+
+/// Reads a recording written by [`Recorder`].
+pub fn read(path: impl AsRef<Path>) -> Result<Vec<RecordedFrame>> {
+    let path = path.as_ref();
+    let file = File::open(path)
+        .with_context(|| format!("Failed to open recording {}.", path.display()))?;
+    let mut frames = vec![];
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let frame = serde_json::from_str(&line).with_context(|| {
+            format!(
+                "Invalid frame on line {} of {}.",
+                number + 1,
+                path.display()
+            )
+        })?;
+        frames.push(frame);
+    }
+    Ok(frames)
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tokio_tungstenite::tungstenite::Message;
+
+    use super::{read, Direction, Frame, Recorder};
+
+    #[tokio::test]
+    async fn records_frames() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "rta-record-{}.jsonl",
+            uuid::Uuid::new_v4().simple()
+        ));
+        let recorder = Recorder::create(&path)?;
+        let messages = [
+            Message::Text("[1,0,0,0]".into()),
+            Message::Ping(7u64.to_be_bytes().to_vec()),
+            Message::Close(None),
+        ];
+        recorder.record(Direction::Outbound, &messages[0]);
+        recorder.clone().record(Direction::Inbound, &messages[1]);
+        recorder.record(Direction::Inbound, &messages[2]);
+        recorder.flush().await;
+
+        let frames = read(&path)?;
+        std::fs::remove_file(&path)?;
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0].direction, Direction::Outbound);
+        assert_eq!(frames[0].frame, Frame::Text("[1,0,0,0]".into()));
+        let replayed = frames.into_iter().map(|f| f.frame.into_message());
+        assert!(replayed.eq(messages));
+        Ok(())
+    }
+}
@@ -0,0 +1,311 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde_json::Value;
+use tokio::{sync::oneshot, time::Instant};
+
+use crate::{
+    backoff::Backoff,
+    bus::EventSource,
+    error::RtaError,
+    resource::{ResourceKind, RtaResource},
+    resync::ResyncHook,
+    subscription::ActiveSubscription,
+};
+
+/// Receives the payload RTA answered a subscribe request with, for a caller
+/// that stops waiting at `deadline`.
+#[derive(Debug)]
+pub(crate) struct SubscribeWaiter {
+    tx: oneshot::Sender<Result<Value, RtaError>>,
+    pub(crate) deadline: Instant,
+}
+
+impl SubscribeWaiter {
+    pub(crate) fn new(timeout: Duration) -> (Self, oneshot::Receiver<Result<Value, RtaError>>) {
+        let (tx, rx) = oneshot::channel();
+        let deadline = Instant::now() + timeout;
+        (Self { tx, deadline }, rx)
+    }
+
+    /// Whether the caller is still waiting `delay` from now.
+    pub(crate) fn outlasts(&self, delay: Duration) -> bool {
+        Instant::now() + delay < self.deadline
+    }
+
+    /// Answers the caller, unless it stopped waiting already.
+    pub(crate) fn send(self, response: Result<Value, RtaError>) {
+        let _ = self.tx.send(response);
+    }
+}
+
+pub(crate) type UnsubscribeWaiter = oneshot::Sender<Result<(), RtaError>>;
+
+#[derive(Debug)]
+pub(crate) struct Unsubscribing {
+    pub(crate) sub_id: i64,
+    pub(crate) uri: String,
+    pub(crate) waiter: Option<UnsubscribeWaiter>,
+}
+
+#[derive(Debug)]
+pub(crate) struct Entry {
+    pub(crate) uri: String,
+    pub(crate) sub_id: Option<i64>,
+    pub(crate) kind: Option<ResourceKind>,
+    pub(crate) resync: Option<ResyncHook>,
+    // Subscribe attempts refused with a transient status in a row.
+    attempts: u32,
+}
+
+/// Subscriptions of one client, keyed by an id that survives reconnects so they
+/// can be replayed while their `sub_id`s change.
+#[derive(Debug, Default)]
+pub(crate) struct Registry {
+    entries: HashMap<u64, Entry>,
+    // seq_id -> key, until RTA answers the subscribe request.
+    pending: HashMap<i64, (u64, Option<SubscribeWaiter>)>,
+    // seq_id -> removed subscription, until RTA answers the unsubscribe request.
+    unsubscribing: HashMap<i64, Unsubscribing>,
+    by_sub_id: HashMap<i64, u64>,
+}
+
+impl Registry {
+    pub(crate) fn insert(&mut self, key: u64, uri: String, resync: Option<ResyncHook>) {
+        let entry = Entry {
+            kind: RtaResource::parse(&uri).map(|resource| resource.kind()),
+            uri,
+            sub_id: None,
+            resync,
+            attempts: 0,
+        };
+        self.entries.insert(key, entry);
+    }
+
+    pub(crate) fn request(&mut self, seq_id: i64, key: u64, waiter: Option<SubscribeWaiter>) {
+        self.pending.insert(seq_id, (key, waiter));
+    }
+
+    /// Takes the request answered by `seq_id`, if it is still wanted.
+    pub(crate) fn resolve(&mut self, seq_id: i64) -> Option<(u64, Option<SubscribeWaiter>)> {
+        self.pending
+            .remove(&seq_id)
+            .filter(|(key, _)| self.entries.contains_key(key))
+    }
+
+    pub(crate) fn request_unsubscribe(&mut self, seq_id: i64, unsubscribing: Unsubscribing) {
+        self.unsubscribing.insert(seq_id, unsubscribing);
+    }
+
+    pub(crate) fn resolve_unsubscribe(&mut self, seq_id: i64) -> Option<Unsubscribing> {
+        self.unsubscribing.remove(&seq_id)
+    }
+
+    pub(crate) fn activate(&mut self, key: u64, sub_id: i64) {
+        if let Some(entry) = self.entries.get_mut(&key) {
+            entry.sub_id = Some(sub_id);
+            entry.attempts = 0;
+            self.by_sub_id.insert(sub_id, key);
+        }
+    }
+
+    /// Counts a transient failure to subscribe `key`, returning its uri, the
+    /// number of the retry and how long to wait for it, or `None` once
+    /// `backoff` gives up.
+    pub(crate) fn schedule_retry(
+        &mut self,
+        key: u64,
+        backoff: &Backoff,
+    ) -> Option<(String, u32, Duration)> {
+        let entry = self.entries.get_mut(&key)?;
+        let delay = backoff.delay(entry.attempts)?;
+        entry.attempts += 1;
+        Some((entry.uri.clone(), entry.attempts, delay))
+    }
+
+    /// Whether a scheduled retry of `key` should still be sent. When the
+    /// subscription was replayed by a reconnect in the meantime, `waiter` is
+    /// handed to the replay instead.
+    pub(crate) fn take_retry(
+        &mut self,
+        key: u64,
+        waiter: Option<SubscribeWaiter>,
+    ) -> Option<Option<SubscribeWaiter>> {
+        if self.entries.get(&key)?.sub_id.is_some() {
+            return None;
+        }
+        if let Some((_, pending)) = self.pending.values_mut().find(|(k, _)| *k == key) {
+            if pending.is_none() {
+                *pending = waiter;
+            }
+            return None;
+        }
+        Some(waiter)
+    }
+
+    pub(crate) fn get(&self, key: u64) -> Option<&Entry> {
+        self.entries.get(&key)
+    }
+
+    /// Tags the events of the subscription registered under `key`.
+    pub(crate) fn source(&self, key: u64) -> Option<EventSource> {
+        let entry = self.entries.get(&key)?;
+        Some(EventSource {
+            key,
+            kind: entry.kind,
+        })
+    }
+
+    pub(crate) fn source_of(&self, sub_id: i64) -> Option<EventSource> {
+        self.source(self.key_of(sub_id)?)
+    }
+
+    pub(crate) fn key_of(&self, sub_id: i64) -> Option<u64> {
+        self.by_sub_id.get(&sub_id).copied()
+    }
+
+    /// The subscription to `uri`, and whether it is live or awaiting an answer.
+    pub(crate) fn find(&self, uri: &str) -> Option<(u64, bool)> {
+        let (key, entry) = self.entries.iter().find(|(_, entry)| entry.uri == uri)?;
+        let requested = entry.sub_id.is_some() || self.pending.values().any(|(k, _)| k == key);
+        Some((*key, requested))
+    }
+
+    pub(crate) fn remove(&mut self, key: u64) -> Option<Entry> {
+        let entry = self.entries.remove(&key)?;
+        if let Some(sub_id) = entry.sub_id {
+            self.by_sub_id.remove(&sub_id);
+        }
+        Some(entry)
+    }
+
+    pub(crate) fn list(&self) -> Vec<ActiveSubscription> {
+        self.entries
+            .iter()
+            .map(|(key, entry)| ActiveSubscription {
+                key: *key,
+                uri: entry.uri.clone(),
+                sub_id: entry.sub_id,
+            })
+            .collect()
+    }
+
+    /// Live subscriptions with a resync hook.
+    pub(crate) fn resyncs(&self) -> Vec<(EventSource, i64, &Entry, &ResyncHook)> {
+        self.entries
+            .iter()
+            .filter_map(|(key, entry)| {
+                let source = EventSource {
+                    key: *key,
+                    kind: entry.kind,
+                };
+                Some((source, entry.sub_id?, entry, entry.resync.as_ref()?))
+            })
+            .collect()
+    }
+
+    /// Forgets every `sub_id` and returns what has to be subscribed again on a
+    /// new connection, keeping the callers still waiting for an answer.
+    pub(crate) fn reset(&mut self) -> Vec<(u64, String, Option<SubscribeWaiter>)> {
+        let mut waiters: HashMap<u64, SubscribeWaiter> = self
+            .pending
+            .drain()
+            .filter_map(|(_, (key, waiter))| Some((key, waiter?)))
+            .collect();
+        // Subscriptions end with their connection.
+        for (_, unsubscribing) in self.unsubscribing.drain() {
+            if let Some(waiter) = unsubscribing.waiter {
+                let _ = waiter.send(Ok(()));
+            }
+        }
+        self.by_sub_id.clear();
+        self.entries
+            .iter_mut()
+            .map(|(key, entry)| {
+                entry.sub_id = None;
+                (*key, entry.uri.clone(), waiters.remove(key))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+
+    use super::{Registry, SubscribeWaiter, Unsubscribing};
+    use crate::{backoff::Backoff, subscription::ActiveSubscription};
+
+    #[test]
+    fn reset_keeps_subscriptions_and_waiters() {
+        let mut registry = Registry::default();
+        let (presence, social) = (0, 1);
+        registry.insert(presence, "https://userpresence.xboxlive.com/".into(), None);
+        registry.insert(social, "https://social.xboxlive.com/".into(), None);
+        registry.request(0, presence, None);
+        registry.request(
+            1,
+            social,
+            Some(SubscribeWaiter::new(Duration::from_secs(1)).0),
+        );
+        let (key, _) = registry.resolve(0).unwrap();
+        registry.activate(key, 42);
+        let (tx, mut rx) = oneshot::channel();
+        let unsubscribing = Unsubscribing {
+            sub_id: 42,
+            uri: "https://userpresence.xboxlive.com/".into(),
+            waiter: Some(tx),
+        };
+        registry.request_unsubscribe(2, unsubscribing);
+
+        let mut replay = registry.reset();
+        replay.sort_by_key(|(key, ..)| *key);
+        assert_eq!(replay.len(), 2);
+        assert!(replay[0].2.is_none());
+        assert!(replay[1].2.is_some());
+        assert!(matches!(rx.try_recv(), Ok(Ok(()))));
+        assert!(registry.source_of(42).is_none());
+        assert!(registry.resolve_unsubscribe(2).is_none());
+
+        registry.remove(presence);
+        assert!(registry.resolve(1).is_none());
+        assert_eq!(
+            registry.list(),
+            vec![ActiveSubscription {
+                key: social,
+                uri: "https://social.xboxlive.com/".into(),
+                sub_id: None,
+            }]
+        );
+    }
+
+    #[test]
+    fn retries_until_replayed() {
+        let backoff = Backoff {
+            max_attempts: Some(2),
+            ..Default::default()
+        };
+        let mut registry = Registry::default();
+        registry.insert(0, "https://social.xboxlive.com/".into(), None);
+        registry.request(0, 0, None);
+        registry.resolve(0);
+
+        let (uri, attempt, delay) = registry.schedule_retry(0, &backoff).unwrap();
+        assert_eq!((uri.as_str(), attempt), ("https://social.xboxlive.com/", 1));
+        assert_eq!(delay, backoff.initial);
+        assert!(registry.take_retry(0, None).is_some());
+        assert!(registry.schedule_retry(0, &backoff).is_some());
+        assert!(registry.schedule_retry(0, &backoff).is_none());
+
+        // A reconnect replays the subscription before the retry is due.
+        registry.reset();
+        registry.request(1, 0, None);
+        assert!(registry
+            .take_retry(0, Some(SubscribeWaiter::new(Duration::from_secs(1)).0))
+            .is_none());
+        let (_, waiter) = registry.resolve(1).unwrap();
+        assert!(waiter.is_some());
+        assert!(registry.take_retry(1, None).is_none());
+    }
+}
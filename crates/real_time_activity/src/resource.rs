@@ -0,0 +1,391 @@
+use anyhow::{bail, Result};
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::message::EventData;
+
+const PRESENCE: &str = "https://userpresence.xboxlive.com/users/";
+const SOCIAL: &str = "http://social.xboxlive.com/users/";
+const ACHIEVEMENTS: &str = "https://achievements.xboxlive.com/users/";
+const SESSION_DIRECTORY: &str = "https://sessiondirectory.xboxlive.com/";
+
+/// Xbox Live resources RTA can be subscribed to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RtaResource {
+    /// Whether a user is online.
+    Presence { xuid: String },
+    /// What a user is playing.
+    RichPresence { xuid: String },
+    /// Friends added to or removed from a user's list.
+    Social { xuid: String },
+    /// Changes to one multiplayer session.
+    MultiplayerSession {
+        scid: String,
+        template: String,
+        name: String,
+    },
+    /// Changes to every multiplayer session the connection is a member of.
+    Connections,
+    /// Progress on a title's achievements.
+    Achievements { xuid: String, scid: String },
+}
+
+/// What an [`RtaResource`] is about, without naming a specific one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ResourceKind {
+    Presence,
+    RichPresence,
+    Social,
+    MultiplayerSession,
+    Connections,
+    Achievements,
+}
+
+/// A subscribe payload or event decoded for its resource.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ResourceData {
+    Presence(PresenceState),
+    RichPresence(RichPresence),
+    Social(SocialChange),
+    Connection(ConnectionInfo),
+    Sessions(EventData),
+    Achievements(AchievementProgress),
+    /// The resource sent nothing, e.g. social subscriptions.
+    Empty,
+}
+
+impl RtaResource {
+    pub fn presence(xuid: impl Into<String>) -> Self {
+        Self::Presence { xuid: xuid.into() }
+    }
+
+    pub fn rich_presence(xuid: impl Into<String>) -> Self {
+        Self::RichPresence { xuid: xuid.into() }
+    }
+
+    pub fn social(xuid: impl Into<String>) -> Self {
+        Self::Social { xuid: xuid.into() }
+    }
+
+    pub fn multiplayer_session(
+        scid: impl Into<String>,
+        template: impl Into<String>,
+        name: impl Into<String>,
+    ) -> Self {
+        Self::MultiplayerSession {
+            scid: scid.into(),
+            template: template.into(),
+            name: name.into(),
+        }
+    }
+
+    pub fn achievements(xuid: impl Into<String>, scid: impl Into<String>) -> Self {
+        Self::Achievements {
+            xuid: xuid.into(),
+            scid: scid.into(),
+        }
+    }
+
+    pub fn kind(&self) -> ResourceKind {
+        match self {
+            Self::Presence { .. } => ResourceKind::Presence,
+            Self::RichPresence { .. } => ResourceKind::RichPresence,
+            Self::Social { .. } => ResourceKind::Social,
+            Self::MultiplayerSession { .. } => ResourceKind::MultiplayerSession,
+            Self::Connections => ResourceKind::Connections,
+            Self::Achievements { .. } => ResourceKind::Achievements,
+        }
+    }
+
+    pub fn uri(&self) -> String {
+        match self {
+            Self::Presence { xuid } => format!("{PRESENCE}xuid({xuid})/devices"),
+            Self::RichPresence { xuid } => format!("{PRESENCE}xuid({xuid})/richpresence"),
+            Self::Social { xuid } => format!("{SOCIAL}xuid({xuid})/friends"),
+            Self::MultiplayerSession {
+                scid,
+                template,
+                name,
+            } => format!(
+                "{SESSION_DIRECTORY}serviceconfigs/{scid}/sessionTemplates/{template}/sessions/{name}"
+            ),
+            Self::Connections => format!("{SESSION_DIRECTORY}connections/"),
+            Self::Achievements { xuid, scid } => {
+                format!("{ACHIEVEMENTS}xuid({xuid})/achievements/{scid}")
+            }
+        }
+    }
+
+    /// The resource `uri` subscribes to, if it is one of the known ones.
+    pub fn parse(uri: &str) -> Option<Self> {
+        fn xuid(path: &str) -> Option<(&str, &str)> {
+            let (xuid, rest) = path.strip_prefix("xuid(")?.split_once(")/")?;
+            Some((xuid, rest))
+        }
+
+        if let Some(path) = uri.strip_prefix(PRESENCE) {
+            return match xuid(path)? {
+                (xuid, "devices") => Some(Self::presence(xuid)),
+                (xuid, "richpresence") => Some(Self::rich_presence(xuid)),
+                _ => None,
+            };
+        }
+        if let Some(path) = uri.strip_prefix(SOCIAL) {
+            return match xuid(path)? {
+                (xuid, "friends") => Some(Self::social(xuid)),
+                _ => None,
+            };
+        }
+        if let Some(path) = uri.strip_prefix(ACHIEVEMENTS) {
+            let (xuid, scid) = xuid(path)?;
+            let scid = scid.strip_prefix("achievements/")?;
+            return (!scid.is_empty()).then(|| Self::achievements(xuid, scid));
+        }
+        let path = uri.strip_prefix(SESSION_DIRECTORY)?;
+        if path.trim_end_matches('/') == "connections" {
+            return Some(Self::Connections);
+        }
+        match path.split('/').collect::<Vec<_>>()[..] {
+            ["serviceconfigs", scid, "sessionTemplates", template, "sessions", name] => {
+                Some(Self::multiplayer_session(scid, template, name))
+            }
+            _ => None,
+        }
+    }
+
+    /// Decodes what RTA answered the subscription with.
+    pub fn decode_payload(&self, payload: &Value) -> Result<ResourceData> {
+        if payload.is_null() {
+            return Ok(ResourceData::Empty);
+        }
+        let decoded = match self {
+            Self::Presence { .. } => ResourceData::Presence(PresenceState::deserialize(payload)?),
+            Self::RichPresence { .. } => {
+                ResourceData::RichPresence(RichPresence::deserialize(payload)?)
+            }
+            Self::Social { .. } => ResourceData::Social(SocialChange::deserialize(payload)?),
+            Self::MultiplayerSession { .. } => {
+                ResourceData::Sessions(EventData::deserialize(payload)?)
+            }
+            Self::Connections => ResourceData::Connection(ConnectionInfo::deserialize(payload)?),
+            Self::Achievements { .. } => {
+                ResourceData::Achievements(AchievementProgress::deserialize(payload)?)
+            }
+        };
+        Ok(decoded)
+    }
+
+    /// Decodes an event for this resource: shoulder taps naming the sessions
+    /// that changed for session resources, the new state for the others.
+    pub fn decode_event(&self, data: &Value) -> Result<ResourceData> {
+        match self {
+            Self::MultiplayerSession { .. } | Self::Connections => {
+                Ok(ResourceData::Sessions(EventData::deserialize(data)?))
+            }
+            _ => self.decode_payload(data),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PresenceState {
+    Online,
+    Offline,
+    Other(String),
+}
+impl<'de> Deserialize<'de> for PresenceState {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(match String::deserialize(deserializer)?.as_str() {
+            "Online" => Self::Online,
+            "Offline" => Self::Offline,
+            other => Self::Other(other.to_owned()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RichPresence {
+    pub xuid: String,
+    pub state: String,
+    pub devices: Vec<PresenceDevice>,
+}
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PresenceDevice {
+    #[serde(rename = "type")]
+    pub device_type: String,
+    pub titles: Vec<PresenceTitle>,
+}
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PresenceTitle {
+    pub id: String,
+    pub name: String,
+    pub state: String,
+    pub placement: String,
+    pub activity: Option<PresenceActivity>,
+}
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PresenceActivity {
+    pub rich_presence: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct SocialChange {
+    pub notification_type: SocialNotification,
+    #[serde(default)]
+    pub xuids: Vec<String>,
+}
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SocialNotification {
+    Added,
+    Changed,
+    Deleted,
+    #[serde(other)]
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ConnectionInfo {
+    /// Joins sessions to this RTA connection, so their changes arrive as
+    /// shoulder taps.
+    pub connection_id: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AchievementProgress {
+    pub service_config_id: String,
+    pub progression: Vec<AchievementProgression>,
+}
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AchievementProgression {
+    pub id: String,
+    pub progress_state: String,
+}
+
+/// A multiplayer session named by a shoulder tap's `Resource`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SessionRef {
+    pub scid: String,
+    pub template: String,
+    pub name: String,
+}
+impl SessionRef {
+    /// Parses `scid~template~name`.
+    pub fn parse(resource: &str) -> Result<Self> {
+        let mut parts = resource.split('~');
+        let (Some(scid), Some(template), Some(name), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Not a session resource: {resource}.");
+        };
+        Ok(Self {
+            scid: scid.to_owned(),
+            template: template.to_owned(),
+            name: name.to_owned(),
+        })
+    }
+
+    pub fn resource(&self) -> RtaResource {
+        RtaResource::multiplayer_session(&*self.scid, &*self.template, &*self.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::{
+        ConnectionInfo, PresenceState, ResourceData, RtaResource, SessionRef, SocialChange,
+        SocialNotification,
+    };
+
+    #[test]
+    fn uris_round_trip() {
+        let resources = [
+            RtaResource::presence("2535400000000000"),
+            RtaResource::rich_presence("2535400000000000"),
+            RtaResource::social("2535400000000000"),
+            RtaResource::multiplayer_session("4fc10100", "MinecraftLobby", "5a1bf1a5"),
+            RtaResource::Connections,
+            RtaResource::achievements("2535400000000000", "4fc10100"),
+        ];
+        for resource in resources {
+            assert_eq!(RtaResource::parse(&resource.uri()), Some(resource));
+        }
+        assert_eq!(
+            RtaResource::parse("https://sessiondirectory.xboxlive.com/connections/"),
+            Some(RtaResource::Connections)
+        );
+        assert_eq!(RtaResource::parse("https://example.com/"), None);
+    }
+
+    #[test]
+    fn decodes_payloads_and_events() -> Result<()> {
+        let connections = RtaResource::Connections;
+        assert_eq!(
+            connections.decode_payload(&json!({ "ConnectionId": "3c8d7d3b" }))?,
+            ResourceData::Connection(ConnectionInfo {
+                connection_id: "3c8d7d3b".into()
+            })
+        );
+        let data = json!({
+            "Ncid": "fd4b0d4e",
+            "ShoulderTaps": [{
+                "Timestamp": "2024-08-20T12:34:56.789Z",
+                "Subscription": "0a0b0c0d",
+                "ResourceType": "MultiplayerSessionDirectory",
+                "Resource": "4fc10100~MinecraftLobby~5a1bf1a5",
+                "Branch": "b8e3c2a0",
+                "ChangeNumber": 7
+            }]
+        });
+        let ResourceData::Sessions(sessions) = connections.decode_event(&data)? else {
+            panic!("expected shoulder taps");
+        };
+        assert_eq!(
+            sessions.shoulder_taps[0].session()?,
+            SessionRef::parse("4fc10100~MinecraftLobby~5a1bf1a5")?
+        );
+
+        let social = RtaResource::social("2535400000000000");
+        assert_eq!(
+            social.decode_payload(&json!({ "NotificationType": "Added", "Xuids": ["1", "2"] }))?,
+            ResourceData::Social(SocialChange {
+                notification_type: SocialNotification::Added,
+                xuids: vec!["1".into(), "2".into()],
+            })
+        );
+        assert_eq!(
+            RtaResource::presence("1").decode_payload(&json!("Online"))?,
+            ResourceData::Presence(PresenceState::Online)
+        );
+        assert!(social.decode_event(&data).is_err());
+        assert!(connections.decode_event(&json!("Offline")).is_err());
+
+        // Events of the other resources carry their new state.
+        assert_eq!(
+            RtaResource::presence("1").decode_event(&json!("Offline"))?,
+            ResourceData::Presence(PresenceState::Offline)
+        );
+        assert_eq!(
+            social.decode_event(&json!({ "NotificationType": "Deleted", "Xuids": ["2"] }))?,
+            ResourceData::Social(SocialChange {
+                notification_type: SocialNotification::Deleted,
+                xuids: vec!["2".into()],
+            })
+        );
+        assert!(SessionRef::parse("not-a-session").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+use std::{fmt, future::Future, sync::Arc};
+
+use anyhow::{bail, Result};
+use futures_util::future::BoxFuture;
+use serde_json::Value;
+
+use crate::builder::Connector;
+
+pub type ResyncFn = dyn Fn(String) -> BoxFuture<'static, Result<Value>> + Send + Sync;
+
+/// How a subscription re-reads its resource after RTA sent a resync, i.e. the
+/// client may have missed events.
+#[derive(Clone)]
+pub enum ResyncHook {
+    /// GET the subscribed URI with the client's Xbox Live credentials.
+    Fetch,
+    /// Called with the subscribed URI.
+    Custom(Arc<ResyncFn>),
+}
+
+impl ResyncHook {
+    pub fn custom<F, Fut>(hook: F) -> Self
+    where
+        F: Fn(String) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value>> + Send + 'static,
+    {
+        Self::Custom(Arc::new(move |uri| Box::pin(hook(uri))))
+    }
+
+    pub(crate) async fn run(&self, uri: String, connector: Option<&Connector>) -> Result<Value> {
+        match self {
+            Self::Fetch => match connector {
+                Some(connector) => connector.fetch(&uri).await,
+                None => bail!("No Xbox Live credentials to fetch {uri} with."),
+            },
+            Self::Custom(hook) => hook(uri).await,
+        }
+    }
+}
+
+impl fmt::Debug for ResyncHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Fetch => f.write_str("Fetch"),
+            Self::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+
+    use super::ResyncHook;
+
+    #[tokio::test]
+    async fn runs_hooks() -> Result<()> {
+        let hook = ResyncHook::custom(|uri| async move { Ok(json!({ "uri": uri })) });
+        let state = hook
+            .run("https://social.xboxlive.com/".into(), None)
+            .await?;
+        assert_eq!(state, json!({ "uri": "https://social.xboxlive.com/" }));
+        assert!(ResyncHook::Fetch
+            .run("https://social.xboxlive.com/".into(), None)
+            .await
+            .is_err());
+        Ok(())
+    }
+}
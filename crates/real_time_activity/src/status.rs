@@ -1,11 +1,12 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Status {
-    Success = 0,
-    UnknownResource = 1,
-    SubscriptionLimitReached = 2,
-    NoResourceData = 3,
-    Throttled = 1001,
-    ServiceUnavailable = 1002,
+    Success,
+    UnknownResource,
+    SubscriptionLimitReached,
+    NoResourceData,
+    Throttled,
+    ServiceUnavailable,
+    Unknown(i64),
 }
 
 impl From<i64> for Status {
@@ -17,7 +18,26 @@ impl From<i64> for Status {
             3 => Self::NoResourceData,
             1001 => Self::Throttled,
             1002 => Self::ServiceUnavailable,
-            n => panic!("[<StatusCode as From<u64>>::from] Invalid value: {n}."),
+            n => Self::Unknown(n),
+        }
+    }
+}
+
+impl Status {
+    /// Whether a request refused with this status may succeed when retried.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, Self::Throttled | Self::ServiceUnavailable)
+    }
+
+    pub fn code(&self) -> i64 {
+        match self {
+            Self::Success => 0,
+            Self::UnknownResource => 1,
+            Self::SubscriptionLimitReached => 2,
+            Self::NoResourceData => 3,
+            Self::Throttled => 1001,
+            Self::ServiceUnavailable => 1002,
+            Self::Unknown(n) => *n,
         }
     }
 }
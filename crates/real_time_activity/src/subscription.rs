@@ -0,0 +1,137 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{Stream, StreamExt};
+use serde_json::Value;
+
+use crate::{
+    bus::EventStream,
+    error::RtaError,
+    event::RtaEvent,
+    resource::{ResourceData, RtaResource},
+    WSWriter,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveSubscription {
+    pub key: u64,
+    pub uri: String,
+    /// `None` while the subscription is waiting to be (re)accepted by RTA.
+    pub sub_id: Option<i64>,
+}
+
+/// A live subscription, answered by RTA with `payload`.
+///
+/// Events for the subscription are delivered here as well as to the client's
+/// other event streams. Dropping it unsubscribes in the background; use
+/// [`Subscription::close`] to wait for RTA to confirm.
+#[derive(Debug)]
+pub struct Subscription {
+    key: u64,
+    uri: String,
+    payload: Value,
+    events: EventStream,
+    writer: WSWriter,
+    closed: bool,
+}
+
+impl Subscription {
+    pub(crate) fn new(
+        key: u64,
+        uri: String,
+        payload: Value,
+        events: EventStream,
+        writer: WSWriter,
+    ) -> Self {
+        Self {
+            key,
+            uri,
+            payload,
+            events,
+            writer,
+            closed: false,
+        }
+    }
+
+    /// Identifies the subscription for the lifetime of the client.
+    #[inline]
+    pub fn key(&self) -> u64 {
+        self.key
+    }
+
+    #[inline]
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// The id RTA currently knows this subscription by, which changes after a
+    /// reconnect and is `None` until the replayed subscription is answered.
+    pub fn sub_id(&self) -> Option<i64> {
+        self.writer.owner(self.key).registry().get(self.key)?.sub_id
+    }
+
+    /// The resource `uri` names, if it is one of the known ones.
+    pub fn resource(&self) -> Option<RtaResource> {
+        RtaResource::parse(&self.uri)
+    }
+
+    /// The state of the resource when the subscription was made.
+    #[inline]
+    pub fn payload(&self) -> &Value {
+        &self.payload
+    }
+
+    /// [`Subscription::payload`] decoded for its resource.
+    pub fn decode_payload(&self) -> anyhow::Result<ResourceData> {
+        let Some(resource) = self.resource() else {
+            anyhow::bail!("{} is not a known resource.", self.uri);
+        };
+        resource.decode_payload(&self.payload)
+    }
+
+    /// The `data` of an [`RtaEvent::Event`] of this subscription decoded for
+    /// its resource.
+    pub fn decode_event(&self, data: &Value) -> anyhow::Result<ResourceData> {
+        let Some(resource) = self.resource() else {
+            anyhow::bail!("{} is not a known resource.", self.uri);
+        };
+        resource.decode_event(data)
+    }
+
+    pub async fn recv(&mut self) -> Option<RtaEvent> {
+        self.events.next().await
+    }
+
+    pub async fn close(mut self) -> Result<(), RtaError> {
+        self.closed = true;
+        self.writer.unsubscribe_key(self.key).await
+    }
+}
+
+impl Stream for Subscription {
+    type Item = RtaEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.get_mut().events.poll_next_unpin(cx)
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        if self.closed {
+            return;
+        }
+        let (writer, key) = (self.writer.clone(), self.key);
+        match tokio::runtime::Handle::try_current() {
+            Ok(runtime) => {
+                runtime.spawn(async move { writer.unsubscribe_key(key).await });
+            }
+            // Without a runtime the connection is gone too; just forget it.
+            Err(_) => {
+                writer.registry().remove(key);
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::{
+    fmt,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures_util::{future, future::BoxFuture, Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_tungstenite::{
+    connect_async,
+    tungstenite::{handshake::client::Request, Error, Message},
+    WebSocketStream,
+};
+
+use crate::record::{Direction, Recorder};
+
+/// Opens the websockets an RTA client talks over.
+///
+/// `request` already carries the RTA uri, the `authorization` header and the
+/// `rta.xboxlive.com.V2` subprotocol, so an implementation only has to pick the
+/// stream to run the handshake on, e.g. with `tokio_tungstenite::client_async`
+/// over a proxied or in-memory stream, and [`split`] the socket.
+pub trait RtaTransport: fmt::Debug + Send + Sync {
+    fn connect(&self, request: Request) -> BoxFuture<'_, anyhow::Result<(FrameSink, FrameStream)>>;
+}
+
+/// Connects over TCP, with TLS from `native-tls` for `wss://` uris.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TcpTransport;
+
+impl RtaTransport for TcpTransport {
+    fn connect(&self, request: Request) -> BoxFuture<'_, anyhow::Result<(FrameSink, FrameStream)>> {
+        Box::pin(async move {
+            let (socket, _) = connect_async(request).await?;
+            Ok(split(socket))
+        })
+    }
+}
+
+/// Sending half of an RTA websocket.
+pub struct FrameSink(Pin<Box<dyn Sink<Message, Error = Error> + Send + Sync>>);
+
+/// Receiving half of an RTA websocket.
+pub struct FrameStream(Pin<Box<dyn Stream<Item = Result<Message, Error>> + Send + Sync>>);
+
+impl FrameSink {
+    pub fn new<S>(sink: S) -> Self
+    where
+        S: Sink<Message, Error = Error> + Send + Sync + 'static,
+    {
+        Self(Box::pin(sink))
+    }
+
+    /// Records every frame before it is sent.
+    pub fn record(self, recorder: Recorder) -> Self {
+        Self::new(self.with(move |message: Message| {
+            recorder.record(Direction::Outbound, &message);
+            future::ready(Ok::<_, Error>(message))
+        }))
+    }
+}
+
+impl FrameStream {
+    pub fn new<S>(stream: S) -> Self
+    where
+        S: Stream<Item = Result<Message, Error>> + Send + Sync + 'static,
+    {
+        Self(Box::pin(stream))
+    }
+
+    /// Records every frame as it is read.
+    pub fn record(self, recorder: Recorder) -> Self {
+        Self::new(self.inspect(move |frame| {
+            if let Ok(message) = frame {
+                recorder.record(Direction::Inbound, message);
+            }
+        }))
+    }
+}
+
+/// Splits an open websocket into the halves an RTA client needs.
+pub fn split<S>(socket: WebSocketStream<S>) -> (FrameSink, FrameStream)
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+{
+    let (sink, stream) = socket.split();
+    (FrameSink::new(sink), FrameStream::new(stream))
+}
+
+impl Sink<Message> for FrameSink {
+    type Error = Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.0.as_mut().poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Error> {
+        self.0.as_mut().start_send(message)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.0.as_mut().poll_flush(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        self.0.as_mut().poll_close(cx)
+    }
+}
+
+impl Stream for FrameStream {
+    type Item = Result<Message, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.as_mut().poll_next(cx)
+    }
+}
+
+impl fmt::Debug for FrameSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FrameSink(..)")
+    }
+}
+
+impl fmt::Debug for FrameStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FrameStream(..)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use futures_util::future::BoxFuture;
+    use tokio_tungstenite::{client_async, tungstenite::handshake::client::Request};
+
+    use super::{split, FrameSink, FrameStream, RtaTransport};
+    use crate::{
+        builder::RtaClientBuilder,
+        mock::{self, MockRta},
+        resource::RtaResource,
+    };
+
+    /// Runs every connection over an in-memory pipe to the mock.
+    #[derive(Debug)]
+    struct InMemory {
+        mock: Arc<MockRta>,
+    }
+
+    impl RtaTransport for InMemory {
+        fn connect(&self, request: Request) -> BoxFuture<'_, Result<(FrameSink, FrameStream)>> {
+            Box::pin(async move {
+                let (client, server) = tokio::io::duplex(64 * 1024);
+                self.mock.serve(server);
+                let (socket, _) = client_async(request, client).await?;
+                Ok(split(socket))
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn connects_through_custom_transports() -> Result<()> {
+        let mock = Arc::new(MockRta::start().await?);
+        let transport = InMemory { mock: mock.clone() };
+        let uri = RtaResource::presence("2535400000000000").uri();
+        let (handle, _) = RtaClientBuilder::new(mock::xbl_auth().await?)
+            .set_uri("ws://rta.invalid/connect".to_owned())
+            .set_transport(transport)
+            .add_subscription(uri.clone())
+            .connect()
+            .await?
+            .listen()?;
+        let _subscription = handle.subscribe(&RtaResource::Connections.uri()).await?;
+        assert_eq!(mock.accepted(), 1);
+        assert_eq!(mock.subscriptions().len(), 2);
+        assert_eq!(mock.authorizations(), ["XBL3.0 x=1234567890;mock"]);
+        Ok(())
+    }
+}
@@ -1,21 +1,59 @@
-use std::{fs, path::PathBuf};
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
 use sha2::{Digest, Sha256};
-use tokio::{
-    fs::{write, File},
-    io::AsyncReadExt,
-    task::spawn_blocking,
-};
+use tokio::task::spawn_blocking;
+use uuid::Uuid;
 
 use crate::{expire::Expire, msa_live::MSATokenResponce, request_token::XSTSToken};
 
+/// Version written into every cache envelope.
+pub const CACHE_VERSION: u32 = 1;
+
+/// `MIGRATIONS[n]` upgrades a version `n` payload to version `n + 1`.
+const MIGRATIONS: [fn(Value) -> Result<Value>; CACHE_VERSION as usize] = [migrate_v0_to_v1];
+
+// Unversioned files hold the raw `Expire<_>` value, which is exactly the v1 payload.
+fn migrate_v0_to_v1(data: Value) -> Result<Value> {
+    Ok(data)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheEnvelope<T> {
+    version: u32,
+    data: T,
+}
+
 #[derive(Debug)]
 pub struct Cache {
     path: PathBuf,
     user_hash: String,
 }
 
+/// Exclusive advisory lock over one user's cache entry, released on drop.
+///
+/// On Unix the lock file is removed again on release.
+#[derive(Debug)]
+pub struct CacheLock {
+    _file: File,
+    path: PathBuf,
+}
+
+impl Drop for CacheLock {
+    fn drop(&mut self) {
+        // Removed while still locked, so that waiters holding the old file
+        // notice it is gone and reopen the path.
+        #[cfg(unix)]
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
 impl Cache {
     pub fn new(path: PathBuf, user_name: &str) -> Self {
         if !path.exists() {
@@ -26,33 +64,130 @@ impl Cache {
             user_hash: create_hash(user_name),
         }
     }
+
     pub async fn get_msa(&self) -> Result<Expire<MSATokenResponce>> {
-        let path = self.path.join(format!("{}_msa-cache.json", self.user_hash));
-        let mut buffer = vec![];
-        File::open(path).await?.read_to_end(&mut buffer).await?;
-        let ret = spawn_blocking(move || serde_json::from_slice(&buffer)).await??;
-        Ok(ret)
+        self.read("msa").await
     }
     pub async fn update_msa(&self, msa: &Expire<MSATokenResponce>) -> Result<()> {
-        let path = self.path.join(format!("{}_msa-cache.json", self.user_hash));
-        let content = serde_json::to_vec(msa)?;
-        write(path, content).await?;
-        Ok(())
+        self.write("msa", msa).await
     }
 
     pub async fn get_xsts(&self) -> Result<Expire<XSTSToken>> {
-        let path = self.path.join(format!("{}_xbl-cache.json", self.user_hash));
-        let mut buffer = vec![];
-        File::open(path).await?.read_to_end(&mut buffer).await?;
-        let ret = spawn_blocking(move || serde_json::from_slice(&buffer)).await??;
-        Ok(ret)
+        self.read("xbl").await
     }
     pub async fn update_xsts(&self, xsts: &Expire<XSTSToken>) -> Result<()> {
-        let path = self.path.join(format!("{}_xbl-cache.json", self.user_hash));
-        let content = serde_json::to_vec(xsts)?;
-        write(path, content).await?;
+        self.write("xbl", xsts).await
+    }
+
+    /// Blocks until no other process holds the lock on this user's `kind` entry.
+    pub async fn lock(&self, kind: &str) -> Result<CacheLock> {
+        let path = self.path.join(format!("{}_{kind}.lock", self.user_hash));
+        let file = spawn_blocking({
+            let path = path.clone();
+            move || lock_file(&path)
+        })
+        .await??;
+        Ok(CacheLock { _file: file, path })
+    }
+
+    pub(crate) async fn read<T>(&self, kind: &str) -> Result<T>
+    where
+        T: DeserializeOwned + Send + 'static,
+    {
+        let path = self.entry_path(kind);
+        let buffer = tokio::fs::read(path).await?;
+        spawn_blocking(move || decode(&buffer)).await?
+    }
+
+    pub(crate) async fn write<T: Serialize>(&self, kind: &str, value: &T) -> Result<()> {
+        let content = serde_json::to_vec(&CacheEnvelope {
+            version: CACHE_VERSION,
+            data: value,
+        })?;
+        let path = self.entry_path(kind);
+        let tmp_path = path.with_extension(format!("tmp-{}", Uuid::new_v4().simple()));
+        spawn_blocking(move || -> Result<()> {
+            let mut file = create_private(&tmp_path)?;
+            file.write_all(&content)?;
+            file.sync_all()?;
+            if let Err(e) = fs::rename(&tmp_path, path) {
+                let _ = fs::remove_file(&tmp_path);
+                return Err(e.into());
+            }
+            Ok(())
+        })
+        .await??;
         Ok(())
     }
+
+    pub(crate) fn entry_path(&self, kind: &str) -> PathBuf {
+        self.path
+            .join(format!("{}_{kind}-cache.json", self.user_hash))
+    }
+}
+
+/// Creates a file only its owner can read, as entries hold refresh tokens
+/// and signing keys.
+fn create_private(path: &Path) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(path)
+}
+
+fn lock_file(path: &Path) -> io::Result<File> {
+    loop {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        file.lock()?;
+        // The previous holder removes the file on release, so a lock taken on
+        // a file opened before that excludes nobody.
+        if is_current(&file, path)? {
+            return Ok(file);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn is_current(file: &File, path: &Path) -> io::Result<bool> {
+    use std::os::unix::fs::MetadataExt;
+
+    let locked = file.metadata()?;
+    match fs::metadata(path) {
+        Ok(current) => Ok(locked.dev() == current.dev() && locked.ino() == current.ino()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+// Lock files are never removed here.
+#[cfg(not(unix))]
+fn is_current(_file: &File, _path: &Path) -> io::Result<bool> {
+    Ok(true)
+}
+
+fn decode<T: DeserializeOwned>(buffer: &[u8]) -> Result<T> {
+    let (version, mut data) = match serde_json::from_slice(buffer)? {
+        Value::Object(mut map) if map.contains_key("version") => {
+            let version = map.get("version").and_then(Value::as_u64);
+            let Some(version) = version.and_then(|v| u32::try_from(v).ok()) else {
+                bail!("Invalid cache version.");
+            };
+            (version, map.remove("data").unwrap_or(Value::Null))
+        }
+        legacy => (0, legacy),
+    };
+    if version > CACHE_VERSION {
+        bail!("Cache version {version} is newer than supported version {CACHE_VERSION}.");
+    }
+    for migrate in &MIGRATIONS[version as usize..] {
+        data = migrate(data)?;
+    }
+    Ok(serde_json::from_value(data)?)
 }
 
 fn create_hash(user_name: &str) -> String {
@@ -64,3 +199,77 @@ fn create_hash(user_name: &str) -> String {
         .collect::<Vec<_>>()
         .join("")
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use uuid::Uuid;
+
+    use super::{Cache, CACHE_VERSION};
+    use crate::{expire::Expire, request_token::XSTSToken};
+
+    fn xsts() -> Expire<XSTSToken> {
+        let token = XSTSToken {
+            gamer_tag: "Ferris".into(),
+            xuid: "2535400000000000".into(),
+            user_hash: "1234567890".into(),
+            token: "token".into(),
+        };
+        Expire::with_duration(token, 3600)
+    }
+
+    #[tokio::test]
+    async fn migrates_unversioned_cache() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("xbl_auth-{}", Uuid::new_v4().simple()));
+        let cache = Cache::new(dir.clone(), "Ferris");
+        tokio::fs::write(cache.entry_path("xbl"), serde_json::to_vec(&xsts())?).await?;
+
+        let migrated = cache.get_xsts().await?;
+        assert_eq!(migrated.gamer_tag, "Ferris");
+
+        cache.update_xsts(&migrated).await?;
+        let raw: serde_json::Value =
+            serde_json::from_slice(&tokio::fs::read(cache.entry_path("xbl")).await?)?;
+        assert_eq!(raw["version"], CACHE_VERSION);
+        assert_eq!(raw["data"]["data"]["xuid"], "2535400000000000");
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn rejects_newer_cache() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("xbl_auth-{}", Uuid::new_v4().simple()));
+        let cache = Cache::new(dir.clone(), "Ferris");
+        let content = serde_json::json!({ "version": CACHE_VERSION + 1, "data": xsts() });
+        tokio::fs::write(cache.entry_path("xbl"), serde_json::to_vec(&content)?).await?;
+
+        assert!(cache.get_xsts().await.is_err());
+
+        // Would read as version 0 if truncated to 32 bits.
+        let content = serde_json::json!({ "version": 1u64 << 32, "data": xsts() });
+        tokio::fs::write(cache.entry_path("xbl"), serde_json::to_vec(&content)?).await?;
+        assert!(cache.get_xsts().await.is_err());
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn removes_lock_files() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("xbl_auth-{}", Uuid::new_v4().simple()));
+        let cache = Cache::new(dir.clone(), "Ferris");
+        let lock = cache.lock("xbl").await?;
+        let waiter = tokio::spawn(async move { cache.lock("xbl").await.map(|_| ()) });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert!(!waiter.is_finished());
+
+        drop(lock);
+        waiter.await??;
+        assert_eq!(std::fs::read_dir(&dir)?.count(), 0);
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
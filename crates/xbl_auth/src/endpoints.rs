@@ -0,0 +1,34 @@
+use crate::request_token::{
+    xbox_device_token::XboxDeviceTokenRequest, xbox_title_token::XboxTitleTokenRequest,
+    xbox_user_token::XboxUserTokenRequest, xsts_token::XstsTokenRequest,
+};
+
+/// Service URLs that can be pointed at a local stand-in.
+#[derive(Debug, Clone)]
+pub struct Endpoints {
+    pub msa_device_code: String,
+    pub msa_token: String,
+    pub user_authentication: String,
+    pub device_authentication: String,
+    pub title_authentication: String,
+    pub xsts_authorization: String,
+    pub minecraft_authentication: String,
+    pub playfab: String,
+    pub minecraft_services: String,
+}
+
+impl Default for Endpoints {
+    fn default() -> Self {
+        Self {
+            msa_device_code: "https://login.live.com/oauth20_connect.srf".into(),
+            msa_token: "https://login.live.com/oauth20_token.srf".into(),
+            user_authentication: XboxUserTokenRequest::USER_REQUEST_URL.into(),
+            device_authentication: XboxDeviceTokenRequest::DEVICE_REQUEST_URL.into(),
+            title_authentication: XboxTitleTokenRequest::TITLE_REQUEST_URL.into(),
+            xsts_authorization: XstsTokenRequest::XSTS_REQUEST_URL.into(),
+            minecraft_authentication: "https://multiplayer.minecraft.net/authentication".into(),
+            playfab: "https://20ca2.playfabapi.com".into(),
+            minecraft_services: "https://authorization.franchise.minecraft-services.net".into(),
+        }
+    }
+}
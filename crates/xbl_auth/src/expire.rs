@@ -34,7 +34,17 @@ impl<V> Expire<V> {
 
     #[inline]
     pub fn is_expired(&self) -> bool {
-        self.expired_at <= now_secs!() + 10
+        self.expires_within(10)
+    }
+
+    #[inline]
+    pub fn expires_within(&self, secs: u64) -> bool {
+        self.expired_at <= now_secs!() + secs
+    }
+
+    #[inline]
+    pub fn expired_at(&self) -> u64 {
+        self.expired_at
     }
 
     #[inline]
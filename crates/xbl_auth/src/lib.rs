@@ -3,7 +3,9 @@ use std::path::PathBuf;
 use anyhow::Result;
 use cache::Cache;
 use crypto::ProofKey;
+use endpoints::Endpoints;
 use expire::Expire;
+use login_page::{LoginBoard, LoginStatus};
 use msa_live::{MSATokenResponce, MsaAuthFlow};
 use p256::ecdsa::SigningKey;
 use rand::thread_rng;
@@ -13,18 +15,27 @@ use request_token::{
     SignedRequestToken, TitleToken, UserToken, XSTSToken,
 };
 use reqwest::Client;
+use tracing::{debug, info, instrument, warn};
 
 pub mod cache;
 pub mod crypto;
+pub mod endpoints;
 pub mod expire;
+pub mod login_page;
+pub mod minecraft;
+pub mod minecraft_services;
 pub mod msa_live;
 pub mod request_token;
+#[cfg(test)]
+mod test_server;
 
 #[derive(Debug)]
 pub struct XBLAuth {
     pub user_name: String,
     cache: Cache,
     client: Client,
+    endpoints: Endpoints,
+    login_board: Option<LoginBoard>,
     signing_key: SigningKey,
     msa_token: Option<Expire<MSATokenResponce>>,
 }
@@ -38,14 +49,59 @@ impl XBLAuth {
             user_name,
             cache,
             client,
+            endpoints: Endpoints::default(),
+            login_board: None,
             signing_key,
             msa_token: None,
         }
     }
 
+    pub fn set_endpoints(mut self, endpoints: Endpoints) -> Self {
+        self.endpoints = endpoints;
+        self
+    }
+
+    pub fn set_login_board(mut self, login_board: LoginBoard) -> Self {
+        self.login_board = Some(login_board);
+        self
+    }
+
     pub async fn get_xbox_token(&mut self) -> Result<Expire<XSTSToken>> {
-        let ret = match self.cache.get_xsts().await {
-            Ok(xsts_cache) if !xsts_cache.is_expired() => return Ok(xsts_cache),
+        self.get_xsts_token(XstsTokenRequest::XBOX_LIVE_RELYING_PARTY, "xbl", 0)
+            .await
+    }
+
+    /// Like [`XBLAuth::get_xbox_token`], but refreshes a cached token that
+    /// expires within `valid_for` seconds.
+    pub async fn get_xbox_token_valid_for(&mut self, valid_for: u64) -> Result<Expire<XSTSToken>> {
+        self.get_xsts_token(XstsTokenRequest::XBOX_LIVE_RELYING_PARTY, "xbl", valid_for)
+            .await
+    }
+
+    // No field holds a token; spans carry the account they act for.
+    #[instrument(skip(self), fields(user = %self.user_name))]
+    pub(crate) async fn get_xsts_token(
+        &mut self,
+        relying_party: &str,
+        cache_kind: &str,
+        valid_for: u64,
+    ) -> Result<Expire<XSTSToken>> {
+        let valid_for = valid_for.max(10);
+        if let Ok(xsts_cache) = self.cache.read::<Expire<XSTSToken>>(cache_kind).await {
+            if !xsts_cache.expires_within(valid_for) {
+                debug!("cache hit");
+                return Ok(xsts_cache);
+            }
+        }
+        debug!("cache miss");
+        // Held until the refreshed token is written, so that processes sharing
+        // the cache directory wait for each other instead of refreshing twice.
+        let _lock = self.cache.lock(cache_kind).await?;
+        let ret = match self.cache.read::<Expire<XSTSToken>>(cache_kind).await {
+            Ok(xsts_cache) if !xsts_cache.expires_within(valid_for) => {
+                debug!("refreshed by another process");
+                return Ok(xsts_cache);
+            }
             _ => {
                 let proofkey = ProofKey::from(*self.signing_key.verifying_key());
                 let user = self.get_user_token().await?;
@@ -53,47 +109,55 @@ impl XBLAuth {
                 let title = self
                     .get_title_token(device.token.clone(), &proofkey)
                     .await?;
-                let xsts = XstsTokenRequest::new(user, device, title, &proofkey)
+                let xsts = XstsTokenRequest::new(&user, &device, &title, &proofkey)
+                    .set_url(self.endpoints.xsts_authorization.clone())
+                    .set_relying_party(relying_party.to_owned())
                     .request_token(&self.signing_key, self.client.clone())
                     .await?;
                 XSTSToken::from_response_token(xsts)?
             }
         };
-        self.cache.update_xsts(&ret).await?;
+        info!(expired_at = ret.expired_at(), "refreshed XSTS token");
+        self.cache.write(cache_kind, &ret).await?;
         Ok(ret)
     }
 
+    #[instrument(skip_all)]
     async fn get_user_token(&mut self) -> Result<UserToken> {
         XboxUserTokenRequest::new(self.fetch_access_token().await?)
+            .set_url(self.endpoints.user_authentication.clone())
             .request_token(&self.signing_key, self.client.clone())
             .await
     }
+    #[instrument(skip_all)]
     async fn get_device_token(&self, proofkey: &ProofKey) -> Result<DeviceToken> {
         XboxDeviceTokenRequest::new(proofkey)
+            .set_url(self.endpoints.device_authentication.clone())
             .request_token(&self.signing_key, self.client.clone())
             .await
     }
+    #[instrument(skip_all)]
     async fn get_title_token(
         &mut self,
         device_token: String,
         proofkey: &ProofKey,
     ) -> Result<TitleToken> {
         XboxTitleTokenRequest::new(self.fetch_access_token().await?, device_token, proofkey)
+            .set_url(self.endpoints.title_authentication.clone())
             .request_token(&self.signing_key, self.client.clone())
             .await
     }
 
+    #[instrument(skip_all, fields(user = %self.user_name))]
     async fn fetch_access_token(&mut self) -> Result<String> {
         let msa_token = match &self.msa_token {
             Some(msa) if !msa.is_expired() => return Ok(msa.access_token.to_owned()),
-            Some(msa) => match self.refresh_msa_token(&msa.refresh_token).await {
-                Ok(v) => v,
-                Err(_) => {
-                    println!("Failed to refresh the MSAToken.");
-                    self.auth_device_code().await?
-                }
-            },
-            None => self.get_msa_cache().await?,
+            // Another process may already have rotated the refresh token, so go
+            // through the cache rather than the copy held in memory.
+            _ => {
+                let _lock = self.cache.lock("msa").await?;
+                self.get_msa_cache().await?
+            }
         };
         let ret = msa_token.access_token.to_owned();
         self.cache.update_msa(&msa_token).await?;
@@ -103,25 +167,118 @@ impl XBLAuth {
 
     async fn get_msa_cache(&self) -> Result<Expire<MSATokenResponce>> {
         match self.cache.get_msa().await {
-            Ok(msa) if !msa.is_expired() => Ok(msa),
+            Ok(msa) if !msa.is_expired() => {
+                debug!("MSA token cache hit");
+                Ok(msa)
+            }
             Ok(msa) => match self.refresh_msa_token(&msa.refresh_token).await {
-                m @ Ok(..) => m,
-                Err(_) => {
-                    println!("Failed to refresh the MSAToken.");
+                m @ Ok(..) => {
+                    info!("refreshed MSA token");
+                    m
+                }
+                Err(e) => {
+                    warn!(error = format!("{e:#}"), "failed to refresh the MSA token");
                     self.auth_device_code().await
                 }
             },
-            Err(_) => self.auth_device_code().await,
+            Err(_) => {
+                debug!("MSA token cache miss");
+                self.auth_device_code().await
+            }
         }
     }
 
     async fn auth_device_code(&self) -> Result<Expire<MSATokenResponce>> {
         let responce = self.start_msa_auth().await?;
+        info!(verification_uri = %responce.verification_uri, "waiting for device code sign-in");
+        // The code is for the user's eyes only, so it is printed rather than logged.
         println!(
             "Open the page \"{}?otc={}\" in a web browser to sign in as {}",
             responce.verification_uri, responce.user_code, self.user_name
         );
-        let msa = self.wait_msa_auth(responce).await?;
-        Ok(msa)
+        if let Some(board) = &self.login_board {
+            board.pending(&self.user_name, &responce);
+        }
+        let msa = self.wait_msa_auth(responce).await;
+        match &msa {
+            Ok(_) => info!("signed in"),
+            Err(e) => warn!(error = format!("{e:#}"), "sign-in failed"),
+        }
+        if let Some(board) = &self.login_board {
+            let status = match &msa {
+                Ok(_) => LoginStatus::SignedIn,
+                Err(e) => LoginStatus::Failed(e.to_string()),
+            };
+            board.finish(&self.user_name, status);
+        }
+        msa
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::{
+        endpoints::Endpoints, expire::Expire, msa_live::MSATokenResponce, test_server::TestServer,
+        XBLAuth,
+    };
+
+    fn token(display_claims: serde_json::Value) -> serde_json::Value {
+        json!({
+            "IssueInstant": "2024-01-01T00:00:00Z",
+            "NotAfter": "2099-01-01T00:00:00Z",
+            "Token": "token",
+            "DisplayClaims": display_claims,
+        })
+    }
+
+    #[tokio::test]
+    async fn xsts_token_from_endpoints() -> Result<()> {
+        let server = TestServer::start(|request| match request.path.as_str() {
+            "/user" => token(json!({ "xui": [{ "uhs": "1234567890" }] })),
+            "/device" => token(json!({ "xdi": { "did": "device", "dcs": "0" } })),
+            "/title" => token(json!({ "xti": { "tid": "1739947436" } })),
+            _ => token(json!({ "xui": [{ "gtg": "Ferris", "uhs": "1234567890", "xid": "2535400000000000" }] })),
+        })
+        .await?;
+        let url = |path: &str| format!("{}{path}", server.uri());
+
+        let dir = std::env::temp_dir().join(format!("xbl_auth-{}", Uuid::new_v4().simple()));
+        let mut auth = XBLAuth::new(dir.clone(), "Ferris".into()).set_endpoints(Endpoints {
+            user_authentication: url("/user"),
+            device_authentication: url("/device"),
+            title_authentication: url("/title"),
+            xsts_authorization: url("/xsts"),
+            ..Default::default()
+        });
+        let msa = MSATokenResponce {
+            token_type: "bearer".into(),
+            scope: String::new(),
+            access_token: "access-token".into(),
+            refresh_token: "refresh-token".into(),
+            user_id: String::new(),
+            expires_in: 3600,
+        };
+        auth.cache
+            .update_msa(&Expire::with_duration(msa, 3600))
+            .await?;
+
+        let xsts = auth.get_xbox_token().await?;
+        assert_eq!(xsts.xuid, "2535400000000000");
+        assert_eq!(xsts.gamer_tag, "Ferris");
+
+        let requests = server.requests();
+        let mut paths: Vec<_> = requests.iter().map(|r| r.path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, ["/device", "/title", "/user", "/xsts"]);
+        let user = requests.iter().find(|r| r.path == "/user").unwrap();
+        assert_eq!(user.body["Properties"]["RpsTicket"], "t=access-token");
+        assert!(requests.iter().all(|r| r.headers.contains_key("signature")));
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
     }
 }
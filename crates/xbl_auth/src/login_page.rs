@@ -0,0 +1,186 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use serde::Serialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tracing::warn;
+
+use crate::{msa_live::DeviceAuthResponse, now_secs};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "state", content = "reason", rename_all = "snake_case")]
+pub enum LoginStatus {
+    Pending,
+    SignedIn,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginEntry {
+    pub user_name: String,
+    pub verification_uri: String,
+    pub user_code: String,
+    pub expired_at: u64,
+    pub status: LoginStatus,
+}
+
+impl LoginEntry {
+    #[inline]
+    pub fn remaining_secs(&self) -> u64 {
+        self.expired_at.saturating_sub(now_secs!())
+    }
+}
+
+/// Accounts going through the device code flow, shared between every `XBLAuth`
+/// it is set on and the page served by [`LoginBoard::serve`].
+#[derive(Debug, Clone, Default)]
+pub struct LoginBoard {
+    entries: Arc<Mutex<BTreeMap<String, LoginEntry>>>,
+}
+
+impl LoginBoard {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn entries(&self) -> Vec<LoginEntry> {
+        self.entries.lock().unwrap().values().cloned().collect()
+    }
+
+    pub(crate) fn pending(&self, user_name: &str, response: &DeviceAuthResponse) {
+        let entry = LoginEntry {
+            user_name: user_name.to_owned(),
+            verification_uri: response.verification_uri.clone(),
+            user_code: response.user_code.clone(),
+            expired_at: now_secs!() + response.expires_in,
+            status: LoginStatus::Pending,
+        };
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(user_name.to_owned(), entry);
+    }
+
+    pub(crate) fn finish(&self, user_name: &str, status: LoginStatus) {
+        if let Some(entry) = self.entries.lock().unwrap().get_mut(user_name) {
+            entry.status = status;
+        }
+    }
+
+    /// Serves the page on `listener` until an accept error occurs.
+    pub async fn serve(self, listener: TcpListener) -> Result<()> {
+        loop {
+            let (socket, _) = listener.accept().await?;
+            let board = self.clone();
+            tokio::spawn(async move {
+                if let Err(e) = board.respond(socket).await {
+                    warn!(error = format!("{e:#}"), "login page request failed");
+                }
+            });
+        }
+    }
+
+    async fn respond(&self, mut socket: TcpStream) -> Result<()> {
+        let mut buf = [0; 1024];
+        let len = socket.read(&mut buf).await?;
+        let request = String::from_utf8_lossy(&buf[..len]);
+        let path = request.split(' ').nth(1).unwrap_or("/");
+        let (status, content_type, body) = match path {
+            "/" => ("200 OK", "text/html; charset=utf-8", self.render()),
+            "/status.json" => (
+                "200 OK",
+                "application/json",
+                serde_json::to_string(&self.entries())?,
+            ),
+            _ => ("404 Not Found", "text/plain", "Not Found".to_owned()),
+        };
+        let response = format!(
+            "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{body}",
+            body.len()
+        );
+        socket.write_all(response.as_bytes()).await?;
+        Ok(())
+    }
+
+    fn render(&self) -> String {
+        let mut rows = String::new();
+        for entry in self.entries() {
+            let (status, remaining) = match &entry.status {
+                LoginStatus::Pending if entry.remaining_secs() == 0 => ("Expired".to_owned(), 0),
+                LoginStatus::Pending => ("Waiting".to_owned(), entry.remaining_secs()),
+                LoginStatus::SignedIn => ("Signed in".to_owned(), 0),
+                LoginStatus::Failed(reason) => (format!("Failed: {reason}"), 0),
+            };
+            let link = format!("{}?otc={}", entry.verification_uri, entry.user_code);
+            let _ = write!(
+                rows,
+                r#"<tr><td>{}</td><td><a href="{}" target="_blank">{}</a></td><td><code>{}</code></td><td>{}:{:02}</td><td>{}</td></tr>"#,
+                escape(&entry.user_name),
+                escape(&link),
+                escape(&entry.verification_uri),
+                escape(&entry.user_code),
+                remaining / 60,
+                remaining % 60,
+                escape(&status),
+            );
+        }
+        format!(
+            r#"<!DOCTYPE html><html><head><meta charset="utf-8"><meta http-equiv="refresh" content="2"><title>Xbox Live sign-in</title></head><body><table><thead><tr><th>Account</th><th>Page</th><th>Code</th><th>Remaining</th><th>Status</th></tr></thead><tbody>{rows}</tbody></table></body></html>"#
+        )
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use tokio::net::TcpListener;
+
+    use super::{LoginBoard, LoginStatus};
+    use crate::msa_live::DeviceAuthResponse;
+
+    #[tokio::test]
+    async fn lists_pending_logins() -> Result<()> {
+        let board = LoginBoard::new();
+        let response = DeviceAuthResponse {
+            user_code: "ABCD1234".into(),
+            device_code: "device-code".into(),
+            verification_uri: "https://www.microsoft.com/link".into(),
+            interval: 5,
+            expires_in: 900,
+        };
+        board.pending("<Ferris>", &response);
+        board.pending("Corro", &response);
+        board.finish("Corro", LoginStatus::SignedIn);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}", listener.local_addr()?);
+        tokio::spawn(board.clone().serve(listener));
+
+        let page = reqwest::get(&url).await?.text().await?;
+        assert!(page.contains("&lt;Ferris&gt;"));
+        assert!(page.contains("https://www.microsoft.com/link?otc=ABCD1234"));
+        assert!(page.contains("Signed in"));
+
+        let status: serde_json::Value = reqwest::get(format!("{url}/status.json"))
+            .await?
+            .json()
+            .await?;
+        assert_eq!(status[0]["user_name"], "<Ferris>");
+        assert_eq!(status[0]["status"]["state"], "pending");
+        Ok(())
+    }
+}
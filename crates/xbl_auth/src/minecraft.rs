@@ -0,0 +1,239 @@
+use anyhow::{anyhow, Result};
+use base64::prelude::*;
+use p384::{ecdsa::SigningKey, pkcs8::EncodePublicKey};
+use rand::thread_rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tracing::{debug, info, instrument};
+
+use crate::{expire::Expire, request_token::_inner::headers, XBLAuth};
+
+pub const MINECRAFT_RELYING_PARTY: &str = "https://multiplayer.minecraft.net/";
+pub const MINECRAFT_VERSION: &str = "1.21.30";
+
+/// The chain a Bedrock client sends in its `Login` packet.
+///
+/// The chain is bound to a fresh key, which is cached along with it as plain
+/// base64. Cache files are only readable by their owner on Unix, but anyone
+/// who can read the cache directory can sign in to servers as the user until
+/// the chain expires.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct MinecraftChain {
+    pub chain: Vec<String>,
+    pub identity: MinecraftIdentity,
+    signing_key: String,
+}
+
+impl MinecraftChain {
+    /// The ES384 key whose public half is the chain's `identityPublicKey`.
+    pub fn signing_key(&self) -> Result<SigningKey> {
+        let bytes = BASE64_STANDARD.decode(&self.signing_key)?;
+        Ok(SigningKey::from_slice(&bytes)?)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MinecraftIdentity {
+    #[serde(rename = "XUID")]
+    pub xuid: String,
+    pub display_name: String,
+    pub identity: String,
+    #[serde(default)]
+    pub title_id: Option<String>,
+}
+
+impl MinecraftIdentity {
+    pub fn from_chain(chain: &[String]) -> Result<Self> {
+        let extra_data = chain
+            .iter()
+            .filter_map(|jwt| jwt_claims(jwt).ok())
+            .find_map(|mut claims| claims.get_mut("extraData").map(Value::take))
+            .ok_or(anyhow!("No identity claims in the Minecraft chain."))?;
+        Ok(serde_json::from_value(extra_data)?)
+    }
+}
+
+/// Decodes a JWT payload without verifying it.
+pub fn jwt_claims(jwt: &str) -> Result<Value> {
+    let payload = jwt.split('.').nth(1).ok_or(anyhow!("Malformed JWT."))?;
+    let decoded = BASE64_URL_SAFE_NO_PAD.decode(payload.trim_end_matches('='))?;
+    Ok(serde_json::from_slice(&decoded)?)
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainResponse {
+    chain: Vec<String>,
+}
+
+impl XBLAuth {
+    #[instrument(skip_all, fields(user = %self.user_name))]
+    pub async fn get_minecraft_chain(&mut self) -> Result<Expire<MinecraftChain>> {
+        if let Ok(chain) = self.cache.read::<Expire<MinecraftChain>>("mc-chain").await {
+            if !chain.is_expired() {
+                debug!("cache hit");
+                return Ok(chain);
+            }
+        }
+        debug!("cache miss");
+        let _lock = self.cache.lock("mc-chain").await?;
+        if let Ok(chain) = self.cache.read::<Expire<MinecraftChain>>("mc-chain").await {
+            if !chain.is_expired() {
+                return Ok(chain);
+            }
+        }
+        let xsts = self
+            .get_xsts_token(MINECRAFT_RELYING_PARTY, "mc-xsts", 0)
+            .await?;
+        let signing_key = SigningKey::random(&mut thread_rng());
+        let identity_public_key =
+            BASE64_STANDARD.encode(signing_key.verifying_key().to_public_key_der()?);
+        let headers = headers! {
+            ("User-Agent", "MCPE/UWP"),
+            ("Client-Version", MINECRAFT_VERSION),
+            ("Authorization", &format!("XBL3.0 x={};{}", xsts.user_hash, xsts.token))
+        };
+        let ChainResponse { chain } = self
+            .client
+            .post(&self.endpoints.minecraft_authentication)
+            .headers(headers)
+            .json(&json!({ "identityPublicKey": identity_public_key }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let expired_at = chain
+            .iter()
+            .filter_map(|jwt| jwt_claims(jwt).ok()?.get("exp")?.as_u64())
+            .min()
+            .ok_or(anyhow!("No expiry in the Minecraft chain."))?;
+        let minecraft_chain = MinecraftChain {
+            identity: MinecraftIdentity::from_chain(&chain)?,
+            chain,
+            signing_key: BASE64_STANDARD.encode(signing_key.to_bytes()),
+        };
+        let ret = Expire::with_timestamp(minecraft_chain, expired_at);
+        info!(expired_at = ret.expired_at(), "refreshed");
+        self.cache.write("mc-chain", &ret).await?;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use base64::prelude::*;
+    use p384::pkcs8::EncodePublicKey;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use super::{MinecraftChain, MinecraftIdentity};
+    use crate::{
+        endpoints::Endpoints, expire::Expire, request_token::XSTSToken, test_server::TestServer,
+        XBLAuth,
+    };
+
+    fn jwt(claims: serde_json::Value) -> String {
+        let header = BASE64_URL_SAFE_NO_PAD.encode(r#"{"alg":"ES384"}"#);
+        let payload = BASE64_URL_SAFE_NO_PAD.encode(claims.to_string());
+        format!("{header}.{payload}.signature")
+    }
+
+    #[test]
+    fn identity_from_chain() -> Result<()> {
+        let chain = vec![
+            jwt(json!({ "certificateAuthority": true, "exp": 1700000000 })),
+            jwt(json!({
+                "exp": 1700000100,
+                "extraData": {
+                    "XUID": "2535400000000000",
+                    "displayName": "Ferris",
+                    "identity": "6d2d5a4e-0000-3000-8000-000000000000",
+                    "sandboxId": "RETAIL",
+                    "titleId": "1739947436"
+                }
+            })),
+        ];
+        let identity = MinecraftIdentity::from_chain(&chain)?;
+        assert_eq!(identity.xuid, "2535400000000000");
+        assert_eq!(identity.display_name, "Ferris");
+        assert_eq!(identity.title_id.as_deref(), Some("1739947436"));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn minecraft_chain() -> Result<()> {
+        let identity = json!({
+            "XUID": "2535400000000000",
+            "displayName": "Ferris",
+            "identity": "6d2d5a4e-0000-3000-8000-000000000000",
+        });
+        let server = TestServer::start(move |_| {
+            json!({ "chain": [
+                jwt(json!({ "exp": 4000000000u64 })),
+                jwt(json!({ "exp": 4000000100u64, "extraData": identity })),
+            ]})
+        })
+        .await?;
+
+        let dir = std::env::temp_dir().join(format!("xbl_auth-{}", Uuid::new_v4().simple()));
+        let mut auth = XBLAuth::new(dir.clone(), "Ferris".into()).set_endpoints(Endpoints {
+            minecraft_authentication: format!("{}/authentication", server.uri()),
+            ..Default::default()
+        });
+        let xsts = XSTSToken {
+            gamer_tag: String::new(),
+            xuid: String::new(),
+            user_hash: "1234567890".into(),
+            token: "mc-xsts".into(),
+        };
+        auth.cache
+            .write("mc-xsts", &Expire::with_duration(xsts, 3600))
+            .await?;
+        // An expired chain is fetched again.
+        let expired = MinecraftChain {
+            chain: vec![],
+            identity: serde_json::from_value(json!({
+                "XUID": "", "displayName": "", "identity": "",
+            }))?,
+            signing_key: String::new(),
+        };
+        auth.cache
+            .write("mc-chain", &Expire::with_timestamp(expired, 0))
+            .await?;
+
+        let chain = auth.get_minecraft_chain().await?;
+        assert_eq!(chain.expired_at(), 4000000000);
+        assert_eq!(chain.identity.display_name, "Ferris");
+        let cached = auth.get_minecraft_chain().await?;
+        assert_eq!(cached.chain, chain.chain);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0].method, "POST");
+        assert_eq!(requests[0].path, "/authentication");
+        assert_eq!(
+            requests[0].headers["authorization"],
+            "XBL3.0 x=1234567890;mc-xsts"
+        );
+        let public_key = chain.signing_key()?.verifying_key().to_public_key_der()?;
+        assert_eq!(
+            requests[0].body["identityPublicKey"],
+            BASE64_STANDARD.encode(public_key)
+        );
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(auth.cache.entry_path("mc-chain"))?
+                .permissions()
+                .mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,213 @@
+use anyhow::Result;
+use chrono::DateTime;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tracing::{debug, info, instrument};
+use uuid::Uuid;
+
+use crate::{expire::Expire, minecraft::MINECRAFT_VERSION, XBLAuth};
+
+pub const PLAYFAB_RELYING_PARTY: &str = "http://playfab.xboxlive.com/";
+pub const PLAYFAB_TITLE_ID: &str = "20CA2";
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayFabToken {
+    pub play_fab_id: String,
+    pub session_ticket: String,
+    pub entity_token: PlayFabEntityToken,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayFabEntityToken {
+    pub entity_token: String,
+    pub token_expiration: String,
+    pub entity: PlayFabEntity,
+}
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct PlayFabEntity {
+    pub id: String,
+    pub r#type: String,
+}
+
+/// Session token for the Minecraft services, sent as the `Authorization` header.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MCToken {
+    pub authorization_header: String,
+    pub valid_until: String,
+    #[serde(default)]
+    pub treatments: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PlayFabResponse<T> {
+    data: T,
+}
+#[derive(Debug, Deserialize)]
+struct MinecraftServicesResponse<T> {
+    result: T,
+}
+
+impl XBLAuth {
+    #[instrument(skip_all, fields(user = %self.user_name))]
+    pub async fn get_playfab_token(&mut self) -> Result<Expire<PlayFabToken>> {
+        if let Ok(playfab) = self.cache.read::<Expire<PlayFabToken>>("playfab").await {
+            if !playfab.is_expired() {
+                debug!("cache hit");
+                return Ok(playfab);
+            }
+        }
+        debug!("cache miss");
+        let _lock = self.cache.lock("playfab").await?;
+        if let Ok(playfab) = self.cache.read::<Expire<PlayFabToken>>("playfab").await {
+            if !playfab.is_expired() {
+                return Ok(playfab);
+            }
+        }
+        let xsts = self
+            .get_xsts_token(PLAYFAB_RELYING_PARTY, "playfab-xsts", 0)
+            .await?;
+        let PlayFabResponse::<PlayFabToken> { data } = self
+            .client
+            .post(format!("{}/Client/LoginWithXbox", self.endpoints.playfab))
+            .json(&json!({
+                "CreateAccount": true,
+                "TitleId": PLAYFAB_TITLE_ID,
+                "XboxToken": format!("XBL3.0 x={};{}", xsts.user_hash, xsts.token),
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let expired_at = DateTime::parse_from_rfc3339(&data.entity_token.token_expiration)?;
+        let ret = Expire::with_timestamp(data, expired_at.timestamp().try_into()?);
+        info!(expired_at = ret.expired_at(), "refreshed");
+        self.cache.write("playfab", &ret).await?;
+        Ok(ret)
+    }
+
+    #[instrument(skip_all, fields(user = %self.user_name))]
+    pub async fn get_minecraft_services_token(&mut self) -> Result<Expire<MCToken>> {
+        if let Ok(mc_token) = self.cache.read::<Expire<MCToken>>("mc-token").await {
+            if !mc_token.is_expired() {
+                debug!("cache hit");
+                return Ok(mc_token);
+            }
+        }
+        debug!("cache miss");
+        let _lock = self.cache.lock("mc-token").await?;
+        if let Ok(mc_token) = self.cache.read::<Expire<MCToken>>("mc-token").await {
+            if !mc_token.is_expired() {
+                return Ok(mc_token);
+            }
+        }
+        let playfab = self.get_playfab_token().await?;
+        let MinecraftServicesResponse::<MCToken> { result } = self
+            .client
+            .post(format!(
+                "{}/api/v1.0/session/start",
+                self.endpoints.minecraft_services
+            ))
+            .json(&json!({
+                "device": {
+                    "applicationType": "MinecraftPE",
+                    "gameVersion": MINECRAFT_VERSION,
+                    "id": Uuid::new_v4().to_string(),
+                    "memory": "8589934592",
+                    "platform": "Windows10",
+                    "playFabTitleId": PLAYFAB_TITLE_ID,
+                    "storePlatform": "uwp.store",
+                    "type": "Windows10"
+                },
+                "user": {
+                    "language": "en",
+                    "languageCode": "en-US",
+                    "regionCode": "US",
+                    "token": playfab.session_ticket,
+                    "tokenType": "PlayFab"
+                }
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let expired_at = DateTime::parse_from_rfc3339(&result.valid_until)?;
+        let ret = Expire::with_timestamp(result, expired_at.timestamp().try_into()?);
+        info!(expired_at = ret.expired_at(), "refreshed");
+        self.cache.write("mc-token", &ret).await?;
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    use crate::{
+        endpoints::Endpoints, expire::Expire, request_token::XSTSToken, test_server::TestServer,
+        XBLAuth,
+    };
+
+    #[tokio::test]
+    async fn minecraft_services_token() -> Result<()> {
+        let server = TestServer::start(|request| {
+            if request.path == "/Client/LoginWithXbox" {
+                json!({ "code": 200, "data": {
+                    "PlayFabId": "ABCDEF",
+                    "SessionTicket": "session-ticket",
+                    "EntityToken": {
+                        "EntityToken": "entity-token",
+                        "TokenExpiration": "2099-01-01T00:00:00Z",
+                        "Entity": { "Id": "0123", "Type": "title_player_account" }
+                    }
+                }})
+            } else {
+                json!({ "result": {
+                    "authorizationHeader": "MCToken mc-token",
+                    "validUntil": "2099-01-01T00:00:00Z",
+                    "treatments": ["mc-example"]
+                }})
+            }
+        })
+        .await?;
+
+        let dir = std::env::temp_dir().join(format!("xbl_auth-{}", Uuid::new_v4().simple()));
+        let mut auth = XBLAuth::new(dir.clone(), "Ferris".into()).set_endpoints(Endpoints {
+            playfab: server.uri().to_owned(),
+            minecraft_services: server.uri().to_owned(),
+            ..Default::default()
+        });
+        let xsts = XSTSToken {
+            gamer_tag: String::new(),
+            xuid: String::new(),
+            user_hash: "1234567890".into(),
+            token: "playfab-xsts".into(),
+        };
+        auth.cache
+            .write("playfab-xsts", &Expire::with_duration(xsts, 3600))
+            .await?;
+
+        let mc_token = auth.get_minecraft_services_token().await?;
+        assert_eq!(mc_token.authorization_header, "MCToken mc-token");
+        assert_eq!(auth.get_playfab_token().await?.play_fab_id, "ABCDEF");
+        auth.get_minecraft_services_token().await?;
+
+        let requests = server.requests();
+        let paths: Vec<_> = requests.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, ["/Client/LoginWithXbox", "/api/v1.0/session/start"]);
+        assert_eq!(
+            requests[0].body["XboxToken"],
+            "XBL3.0 x=1234567890;playfab-xsts"
+        );
+        assert_eq!(requests[1].body["user"]["token"], "session-ticket");
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
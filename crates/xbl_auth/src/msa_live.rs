@@ -9,9 +9,6 @@ use crate::{expire::Expire, XBLAuth};
 const SCOPE: &str = "service::user.auth.xboxlive.com::MBI_SSL";
 const SWITCH_CLIENT_ID: &str = "00000000441cc96b";
 
-const LIVE_DEVICE_CODE_REQUEST: &str = "https://login.live.com/oauth20_connect.srf";
-const LIVE_ACCESS_TOKEN_REQUEST: &str = "https://login.live.com/oauth20_token.srf";
-
 pub trait MsaAuthFlow {
     fn start_msa_auth(
         &self,
@@ -30,7 +27,7 @@ impl MsaAuthFlow for XBLAuth {
     async fn start_msa_auth(&self) -> Result<DeviceAuthResponse> {
         let ret = self
             .client
-            .post(LIVE_DEVICE_CODE_REQUEST)
+            .post(&self.endpoints.msa_device_code)
             .form(&[
                 ("scope", SCOPE),
                 ("client_id", SWITCH_CLIENT_ID),
@@ -54,7 +51,7 @@ impl MsaAuthFlow for XBLAuth {
             }
             let response = self
                 .client
-                .post(LIVE_ACCESS_TOKEN_REQUEST)
+                .post(&self.endpoints.msa_token)
                 .form(&[
                     ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
                     ("device_code", &auth_response.device_code),
@@ -74,7 +71,7 @@ impl MsaAuthFlow for XBLAuth {
     async fn refresh_msa_token(&self, refresh_token: &str) -> Result<Expire<MSATokenResponce>> {
         let response: MSATokenResponce = self
             .client
-            .post(LIVE_ACCESS_TOKEN_REQUEST)
+            .post(&self.endpoints.msa_token)
             .form(&[
                 ("scope", SCOPE),
                 ("grant_type", "refresh_token"),
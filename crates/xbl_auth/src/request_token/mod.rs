@@ -43,7 +43,25 @@ impl XSTSToken {
             xuid: xid,
             token: value.token,
         };
-        Ok(Expire::with_timestamp(xsts_token, expired_at as u64))
+        Ok(Expire::with_timestamp(xsts_token, expired_at.try_into()?))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct TokenRequestBody<P> {
+    pub properties: P,
+    pub relying_party: String,
+    pub token_type: String,
+}
+impl<P> TokenRequestBody<P> {
+    #[inline]
+    pub fn new(properties: P, relying_party: &str) -> Self {
+        Self {
+            properties,
+            relying_party: relying_party.to_owned(),
+            token_type: "JWT".to_owned(),
+        }
     }
 }
 
@@ -57,7 +75,13 @@ pub struct ResponseToken<T> {
 }
 
 pub trait SignedRequestToken {
+    type Properties: Serialize;
     type DisplayClaims: Debug;
+    fn body(&self) -> &TokenRequestBody<Self::Properties>;
+    /// The exact payload that `request_token` signs and sends.
+    fn signed_body(&self) -> Result<String> {
+        Ok(serde_json::to_string(self.body())?)
+    }
     fn request_token(
         &self,
         signer: &SigningKey,
@@ -4,20 +4,67 @@ use uuid::Uuid;
 
 use crate::{crypto::ProofKey, request_token::_inner::headers};
 
-use super::{generate_signature, SignedRequestToken};
+use super::{generate_signature, SignedRequestToken, TokenRequestBody};
 
 #[derive(Debug)]
-pub struct XboxDeviceTokenRequest<'a> {
-    proofkey: &'a ProofKey,
+pub struct XboxDeviceTokenRequest {
+    body: TokenRequestBody<XDeviceTokenProperties>,
+    url: String,
 }
 
-impl XboxDeviceTokenRequest<'_> {
+impl XboxDeviceTokenRequest {
     pub const DEVICE_REQUEST_URL: &'static str =
         "https://device.auth.xboxlive.com/device/authenticate";
     #[inline]
-    pub fn new(proofkey: &ProofKey) -> XboxDeviceTokenRequest<'_> {
-        XboxDeviceTokenRequest { proofkey }
+    pub fn new(proofkey: &ProofKey) -> XboxDeviceTokenRequest {
+        let id = format!("{{{}}}", Uuid::new_v4());
+        let properties = XDeviceTokenProperties {
+            auth_method: "ProofOfPossession".to_owned(),
+            serial_number: id.clone(),
+            id,
+            version: "0.0.0".to_owned(),
+            device_type: "Nintendo".to_owned(),
+            proof_key: proofkey.clone(),
+        };
+        XboxDeviceTokenRequest {
+            body: TokenRequestBody::new(properties, "http://auth.xboxlive.com"),
+            url: Self::DEVICE_REQUEST_URL.to_owned(),
+        }
+    }
+
+    /// Sends the request to `url` instead of [`Self::DEVICE_REQUEST_URL`].
+    pub fn set_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
+    }
+
+    pub fn set_device_id(mut self, id: Uuid) -> Self {
+        let id = format!("{{{id}}}");
+        self.body.properties.serial_number = id.clone();
+        self.body.properties.id = id;
+        self
+    }
+
+    pub fn set_device_type(mut self, device_type: String) -> Self {
+        self.body.properties.device_type = device_type;
+        self
     }
+
+    pub fn set_version(mut self, version: String) -> Self {
+        self.body.properties.version = version;
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct XDeviceTokenProperties {
+    pub auth_method: String,
+    pub id: String,
+    pub serial_number: String,
+    pub version: String,
+    pub device_type: String,
+    pub proof_key: ProofKey,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -30,38 +77,28 @@ pub struct XdtClaim {
     pub dcs: String,
 }
 
-impl SignedRequestToken for XboxDeviceTokenRequest<'_> {
+impl SignedRequestToken for XboxDeviceTokenRequest {
+    type Properties = XDeviceTokenProperties;
     type DisplayClaims = XDeviceDisplayClaims;
 
+    fn body(&self) -> &TokenRequestBody<Self::Properties> {
+        &self.body
+    }
+
     async fn request_token(
         &self,
         signer: &SigningKey,
         client: reqwest::Client,
     ) -> anyhow::Result<super::ResponseToken<Self::DisplayClaims>> {
-        let body = format!(
-            r#"{{
-            "Properties": {{
-                "AuthMethod": "ProofOfPossession",
-                "Id": "{{{0}}}",
-                "SerialNumber": "{{{0}}}",
-                "Version": "0.0.0",
-                "DeviceType": "Nintendo",
-                "ProofKey": {1}
-            }},
-            "RelyingParty": "http://auth.xboxlive.com",
-            "TokenType": "JWT"
-        }}"#,
-            Uuid::new_v4(),
-            serde_json::to_string(&self.proofkey)?
-        );
-        let sig = generate_signature(signer, &Self::DEVICE_REQUEST_URL.parse()?, &body)?;
+        let body = self.signed_body()?;
+        let sig = generate_signature(signer, &self.url.parse()?, &body)?;
         let headers = headers! {
             ("Cache-Control", "no-store, must-revalidate, no-cache"),
             ("x-xbl-contract-version", "1"),
             ("Signature", &sig)
         };
         let ret = client
-            .post(Self::DEVICE_REQUEST_URL)
+            .post(&self.url)
             .headers(headers)
             .body(body)
             .send()
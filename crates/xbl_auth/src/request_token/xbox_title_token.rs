@@ -5,16 +5,15 @@ use crate::{
     request_token::{_inner::headers, generate_signature},
 };
 
-use super::SignedRequestToken;
+use super::{SignedRequestToken, TokenRequestBody};
 
 #[derive(Debug)]
-pub struct XboxTitleTokenRequest<'a> {
-    msa_access_token: String,
-    device_token: String,
-    proofkey: &'a ProofKey,
+pub struct XboxTitleTokenRequest {
+    body: TokenRequestBody<XTitleTokenProperties>,
+    url: String,
 }
 
-impl XboxTitleTokenRequest<'_> {
+impl XboxTitleTokenRequest {
     pub const TITLE_REQUEST_URL: &'static str =
         "https://title.auth.xboxlive.com/title/authenticate";
     #[inline]
@@ -22,13 +21,43 @@ impl XboxTitleTokenRequest<'_> {
         msa_access_token: String,
         device_token: String,
         proofkey: &ProofKey,
-    ) -> XboxTitleTokenRequest<'_> {
-        XboxTitleTokenRequest {
-            msa_access_token,
+    ) -> XboxTitleTokenRequest {
+        let properties = XTitleTokenProperties {
+            auth_method: "RPS".to_owned(),
             device_token,
-            proofkey,
+            rps_ticket: format!("t={msa_access_token}"),
+            site_name: "user.auth.xboxlive.com".to_owned(),
+            proof_key: proofkey.clone(),
+            title_id: None,
+        };
+        XboxTitleTokenRequest {
+            body: TokenRequestBody::new(properties, "http://auth.xboxlive.com"),
+            url: Self::TITLE_REQUEST_URL.to_owned(),
         }
     }
+
+    /// Sends the request to `url` instead of [`Self::TITLE_REQUEST_URL`].
+    pub fn set_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
+    }
+
+    pub fn set_title_id(mut self, title_id: String) -> Self {
+        self.body.properties.title_id = Some(title_id);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct XTitleTokenProperties {
+    pub auth_method: String,
+    pub device_token: String,
+    pub rps_ticket: String,
+    pub site_name: String,
+    pub proof_key: ProofKey,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -40,38 +69,28 @@ pub struct XttClaim {
     pub tid: String,
 }
 
-impl SignedRequestToken for XboxTitleTokenRequest<'_> {
+impl SignedRequestToken for XboxTitleTokenRequest {
+    type Properties = XTitleTokenProperties;
     type DisplayClaims = XTitleDisplayClaims;
 
+    fn body(&self) -> &TokenRequestBody<Self::Properties> {
+        &self.body
+    }
+
     async fn request_token(
         &self,
         signer: &p256::ecdsa::SigningKey,
         client: reqwest::Client,
     ) -> anyhow::Result<super::ResponseToken<Self::DisplayClaims>> {
-        let body = format!(
-            r#"{{
-            "Properties": {{
-                "AuthMethod": "RPS",
-                "DeviceToken": "{}",
-                "RpsTicket": "t={}",
-                "SiteName": "user.auth.xboxlive.com",
-                "ProofKey": {}
-            }},
-            "RelyingParty": "http://auth.xboxlive.com",
-            "TokenType": "JWT"
-        }}"#,
-            self.device_token,
-            self.msa_access_token,
-            serde_json::to_string(&self.proofkey)?
-        );
-        let sig = generate_signature(signer, &Self::TITLE_REQUEST_URL.parse()?, &body)?;
+        let body = self.signed_body()?;
+        let sig = generate_signature(signer, &self.url.parse()?, &body)?;
         let headers = headers! {
             ("Cache-Control", "no-store, must-revalidate, no-cache"),
             ("x-xbl-contract-version", "1"),
             ("Signature", &sig)
         };
         let ret = client
-            .post(Self::TITLE_REQUEST_URL)
+            .post(&self.url)
             .headers(headers)
             .body(body)
             .send()
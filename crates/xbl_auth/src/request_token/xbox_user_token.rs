@@ -1,21 +1,49 @@
 use p256::ecdsa::SigningKey;
 use serde::{Deserialize, Serialize};
 
-use super::{SignedRequestToken, _inner::headers, generate_signature};
+use super::{_inner::headers, generate_signature, SignedRequestToken, TokenRequestBody};
 
 #[derive(Debug)]
 pub struct XboxUserTokenRequest {
-    msa_access_token: String,
+    body: TokenRequestBody<XUserTokenProperties>,
+    url: String,
 }
 
 impl XboxUserTokenRequest {
     pub const USER_REQUEST_URL: &'static str = "https://user.auth.xboxlive.com/user/authenticate";
     #[inline]
     pub fn new(msa_access_token: String) -> Self {
-        Self { msa_access_token }
+        let properties = XUserTokenProperties {
+            auth_method: "RPS".to_owned(),
+            site_name: "user.auth.xboxlive.com".to_owned(),
+            rps_ticket: format!("t={msa_access_token}"),
+        };
+        Self {
+            body: TokenRequestBody::new(properties, "http://auth.xboxlive.com"),
+            url: Self::USER_REQUEST_URL.to_owned(),
+        }
+    }
+
+    /// Sends the request to `url` instead of [`Self::USER_REQUEST_URL`].
+    pub fn set_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
+    }
+
+    pub fn set_relying_party(mut self, relying_party: String) -> Self {
+        self.body.relying_party = relying_party;
+        self
     }
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct XUserTokenProperties {
+    pub auth_method: String,
+    pub site_name: String,
+    pub rps_ticket: String,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct XUserDisplayClaims {
     pub xui: [XutClaim; 1],
@@ -26,26 +54,20 @@ pub struct XutClaim {
 }
 
 impl SignedRequestToken for XboxUserTokenRequest {
+    type Properties = XUserTokenProperties;
     type DisplayClaims = XUserDisplayClaims;
 
+    fn body(&self) -> &TokenRequestBody<Self::Properties> {
+        &self.body
+    }
+
     async fn request_token(
         &self,
         signer: &SigningKey,
         client: reqwest::Client,
     ) -> anyhow::Result<super::ResponseToken<Self::DisplayClaims>> {
-        let body = format!(
-            r#"{{
-            "Properties": {{
-                "AuthMethod": "RPS",
-                "SiteName": "user.auth.xboxlive.com",
-                "RpsTicket": "t={}"
-            }},
-            "RelyingParty": "http://auth.xboxlive.com",
-            "TokenType": "JWT"
-        }}"#,
-            self.msa_access_token
-        );
-        let sig = generate_signature(signer, &Self::USER_REQUEST_URL.parse()?, &body)?;
+        let body = self.signed_body()?;
+        let sig = generate_signature(signer, &self.url.parse()?, &body)?;
         let headers = headers! {
             ("Accept", "application/json"),
             ("Content-Type", "application/json"),
@@ -54,7 +76,7 @@ impl SignedRequestToken for XboxUserTokenRequest {
             ("Signature", &sig)
         };
         let ret = client
-            .post(Self::USER_REQUEST_URL)
+            .post(&self.url)
             .headers(headers)
             .body(body)
             .send()
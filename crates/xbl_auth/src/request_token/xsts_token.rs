@@ -5,32 +5,72 @@ use crate::{
     request_token::{_inner::headers, generate_signature},
 };
 
-use super::{DeviceToken, ResponseToken, SignedRequestToken, TitleToken, UserToken};
+use super::{
+    DeviceToken, ResponseToken, SignedRequestToken, TitleToken, TokenRequestBody, UserToken,
+};
 
 #[derive(Debug)]
-pub struct XstsTokenRequest<'a> {
-    user_token: UserToken,
-    device_token: DeviceToken,
-    title_token: TitleToken,
-    proofkey: &'a ProofKey,
+pub struct XstsTokenRequest {
+    body: TokenRequestBody<XstsTokenProperties>,
+    url: String,
 }
 
-impl XstsTokenRequest<'_> {
+impl XstsTokenRequest {
     pub const XSTS_REQUEST_URL: &'static str = "https://xsts.auth.xboxlive.com/xsts/authorize";
+    pub const XBOX_LIVE_RELYING_PARTY: &'static str = "http://xboxlive.com";
     #[inline]
     pub fn new(
-        user_token: UserToken,
-        device_token: DeviceToken,
-        title_token: TitleToken,
+        user_token: &UserToken,
+        device_token: &DeviceToken,
+        title_token: &TitleToken,
         proofkey: &ProofKey,
-    ) -> XstsTokenRequest<'_> {
+    ) -> XstsTokenRequest {
+        let properties = XstsTokenProperties {
+            user_tokens: vec![user_token.token.clone()],
+            device_token: device_token.token.clone(),
+            title_token: title_token.token.clone(),
+            proof_key: proofkey.clone(),
+            sandbox_id: "RETAIL".to_owned(),
+            optional_display_claims: vec![],
+        };
         XstsTokenRequest {
-            user_token,
-            device_token,
-            title_token,
-            proofkey,
+            body: TokenRequestBody::new(properties, Self::XBOX_LIVE_RELYING_PARTY),
+            url: Self::XSTS_REQUEST_URL.to_owned(),
         }
     }
+
+    /// Sends the request to `url` instead of [`Self::XSTS_REQUEST_URL`].
+    pub fn set_url(mut self, url: String) -> Self {
+        self.url = url;
+        self
+    }
+
+    pub fn set_relying_party(mut self, relying_party: String) -> Self {
+        self.body.relying_party = relying_party;
+        self
+    }
+
+    pub fn set_sandbox_id(mut self, sandbox_id: String) -> Self {
+        self.body.properties.sandbox_id = sandbox_id;
+        self
+    }
+
+    pub fn add_optional_display_claim(mut self, claim: String) -> Self {
+        self.body.properties.optional_display_claims.push(claim);
+        self
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct XstsTokenProperties {
+    pub user_tokens: Vec<String>,
+    pub device_token: String,
+    pub title_token: String,
+    pub proof_key: ProofKey,
+    pub sandbox_id: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub optional_display_claims: Vec<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -39,45 +79,36 @@ pub struct XstsDisplayClaims {
 }
 #[derive(Debug, Serialize, Deserialize)]
 pub struct XstsClaim {
+    // Only issued for the Xbox Live relying party.
+    #[serde(default)]
     pub gtg: String,
+    #[serde(default)]
     pub xid: String,
     pub uhs: String,
 }
 
-impl SignedRequestToken for XstsTokenRequest<'_> {
+impl SignedRequestToken for XstsTokenRequest {
+    type Properties = XstsTokenProperties;
     type DisplayClaims = XstsDisplayClaims;
 
+    fn body(&self) -> &TokenRequestBody<Self::Properties> {
+        &self.body
+    }
+
     async fn request_token(
         &self,
         signer: &p256::ecdsa::SigningKey,
         client: reqwest::Client,
     ) -> anyhow::Result<ResponseToken<Self::DisplayClaims>> {
-        pub const RELYING_PARTY: &str = "http://xboxlive.com";
-        let body = format!(
-            r#"{{
-            "Properties": {{
-                "UserTokens": ["{}"],
-                "DeviceToken": "{}",
-                "TitleToken": "{}",
-                "ProofKey": {},
-                "SandboxId": "RETAIL"
-            }},
-            "RelyingParty": "{RELYING_PARTY}",
-            "TokenType": "JWT"
-        }}"#,
-            self.user_token.token,
-            self.device_token.token,
-            self.title_token.token,
-            serde_json::to_string(&self.proofkey)?
-        );
-        let sig = generate_signature(signer, &Self::XSTS_REQUEST_URL.parse()?, &body)?;
+        let body = self.signed_body()?;
+        let sig = generate_signature(signer, &self.url.parse()?, &body)?;
         let headers = headers! {
             ("Cache-Control", "no-store, must-revalidate, no-cache"),
             ("x-xbl-contract-version", "1"),
             ("Signature", &sig)
         };
         let ret = client
-            .post(Self::XSTS_REQUEST_URL)
+            .post(&self.url)
             .headers(headers)
             .body(body)
             .send()
@@ -87,3 +118,64 @@ impl SignedRequestToken for XstsTokenRequest<'_> {
         Ok(ret)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use p256::ecdsa::SigningKey;
+    use rand::thread_rng;
+    use serde_json::{json, Value};
+
+    use super::XstsTokenRequest;
+    use crate::{
+        crypto::ProofKey,
+        request_token::{ResponseToken, SignedRequestToken},
+    };
+
+    fn response<T>(token: &str, display_claims: T) -> ResponseToken<T> {
+        ResponseToken {
+            issue_instant: "2024-01-01T00:00:00Z".into(),
+            not_after: "2024-01-02T00:00:00Z".into(),
+            token: token.into(),
+            display_claims,
+        }
+    }
+
+    #[test]
+    fn xsts_body() -> Result<()> {
+        let signing_key = SigningKey::random(&mut thread_rng());
+        let proofkey = ProofKey::from(*signing_key.verifying_key());
+        let user = response(
+            "user\"token",
+            serde_json::from_value(json!({ "xui": [{ "uhs": "0" }] }))?,
+        );
+        let device = response(
+            "device",
+            serde_json::from_value(json!({ "xdi": { "did": "0", "dcs": "0" } }))?,
+        );
+        let title = response(
+            "title",
+            serde_json::from_value(json!({ "xti": { "tid": "0" } }))?,
+        );
+
+        let request = XstsTokenRequest::new(&user, &device, &title, &proofkey)
+            .set_relying_party("rp://api.minecraftservices.com/".into())
+            .add_optional_display_claim("mgt".into());
+        let body: Value = serde_json::from_str(&request.signed_body()?)?;
+        assert_eq!(body["RelyingParty"], "rp://api.minecraftservices.com/");
+        assert_eq!(body["TokenType"], "JWT");
+        assert_eq!(body["Properties"]["UserTokens"], json!(["user\"token"]));
+        assert_eq!(body["Properties"]["SandboxId"], "RETAIL");
+        assert_eq!(body["Properties"]["OptionalDisplayClaims"], json!(["mgt"]));
+        assert_eq!(body["Properties"]["ProofKey"]["crv"], "P-256");
+
+        let request = XstsTokenRequest::new(&user, &device, &title, &proofkey);
+        let body: Value = serde_json::from_str(&request.signed_body()?)?;
+        assert_eq!(
+            body["RelyingParty"],
+            XstsTokenRequest::XBOX_LIVE_RELYING_PARTY
+        );
+        assert!(body["Properties"].get("OptionalDisplayClaims").is_none());
+        Ok(())
+    }
+}
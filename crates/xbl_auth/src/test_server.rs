@@ -0,0 +1,130 @@
+//! A local HTTP server answering JSON requests, for tests.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::{Context, Result};
+use serde_json::Value;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+#[derive(Debug, Clone)]
+pub(crate) struct Request {
+    pub method: String,
+    pub path: String,
+    /// Keyed by lowercase name.
+    pub headers: HashMap<String, String>,
+    pub body: Value,
+}
+
+type Respond = dyn Fn(&Request) -> Value + Send + Sync;
+
+/// Records every request and answers it with `200 OK` and the JSON returned
+/// by `respond`. Requests are checked by the test once they were made, so a
+/// mismatch fails the test rather than a server task.
+pub(crate) struct TestServer {
+    uri: String,
+    requests: Arc<Mutex<Vec<Request>>>,
+    task: JoinHandle<()>,
+}
+
+impl TestServer {
+    pub async fn start(
+        respond: impl Fn(&Request) -> Value + Send + Sync + 'static,
+    ) -> Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let uri = format!("http://{}", listener.local_addr()?);
+        let requests = Arc::new(Mutex::new(vec![]));
+        let respond: Arc<Respond> = Arc::new(respond);
+        let task = tokio::spawn({
+            let requests = requests.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, requests.clone(), respond.clone()));
+                }
+            }
+        });
+        Ok(Self {
+            uri,
+            requests,
+            task,
+        })
+    }
+
+    pub fn uri(&self) -> &str {
+        &self.uri
+    }
+
+    /// Every request answered so far, oldest first.
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    requests: Arc<Mutex<Vec<Request>>>,
+    respond: Arc<Respond>,
+) -> Result<()> {
+    let mut buf = vec![0; 64 * 1024];
+    let mut len = 0;
+    let (head, body_start) = loop {
+        let read = stream.read(&mut buf[len..]).await?;
+        anyhow::ensure!(read > 0, "Connection closed before the request ended.");
+        len += read;
+        let text = String::from_utf8_lossy(&buf[..len]);
+        if let Some(i) = text.find("\r\n\r\n") {
+            break (text[..i].to_owned(), i + 4);
+        }
+    };
+    let headers: HashMap<String, String> = head
+        .lines()
+        .skip(1)
+        .filter_map(|l| l.split_once(':'))
+        .map(|(k, v)| (k.trim().to_lowercase(), v.trim().to_owned()))
+        .collect();
+    let content_length = headers
+        .get("content-length")
+        .map(|v| v.parse::<usize>())
+        .transpose()?
+        .unwrap_or(0);
+    buf.resize(buf.len().max(body_start + content_length), 0);
+    while len < body_start + content_length {
+        let read = stream.read(&mut buf[len..]).await?;
+        anyhow::ensure!(read > 0, "Connection closed before the request body ended.");
+        len += read;
+    }
+    let mut request_line = head.split(' ');
+    let method = request_line.next().unwrap_or_default().to_owned();
+    let path = request_line
+        .next()
+        .context("No path in the request.")?
+        .to_owned();
+    let body = &buf[body_start..len];
+    let request = Request {
+        method,
+        path,
+        headers,
+        body: serde_json::from_slice(body).unwrap_or(Value::Null),
+    };
+
+    let response = respond(&request).to_string();
+    requests.lock().unwrap().push(request);
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    Ok(())
+}